@@ -0,0 +1,285 @@
+//! Selective disclosure of a single fixed-denomination note.
+//!
+//! A note's owner can show an auditor which deposit funded which withdrawal
+//! by handing over the note itself. The auditor recomputes, with the pool's
+//! hash function,
+//!   commitment = H(secret || nullifier_preimage)  → matches the `DepositEvent`
+//!   nullifier  = H(nullifier_preimage)            → matches the `WithdrawEvent`
+//! and learns nothing about any other note in the pool.
+//!
+//! An audit memo carries the same opening, sealed to a viewing key at deposit
+//! time: `secret || nullifier_preimage || pool_id || memo` (`sealAuditMemo` in
+//! the SDK). Whoever holds the viewing key decrypts it into an `AuditOpening`
+//! and checks it against the events the same way.
+//!
+//! Only disclose spent notes: an unspent note is all it takes to withdraw.
+//!
+//! Used by owners and auditors off-chain, so it is not built for the program.
+
+use anchor_lang::prelude::*;
+
+use crate::{
+    compute_commitment, compute_nullifier, ClawLinkError, DepositEvent, HashFunction,
+    WithdrawEvent,
+};
+
+/// A disclosed note and the transactions it links.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct NoteDisclosure {
+    pub pool_id: u8,
+    pub hash_function: HashFunction,
+    /// Value of the note: the pool's denomination
+    pub amount: u64,
+    pub secret: [u8; 32],
+    pub nullifier_preimage: [u8; 32],
+    pub commitment: [u8; 32],
+    pub nullifier_hash: [u8; 32],
+    /// Signature of the deposit transaction (base58)
+    pub deposit_tx: String,
+    /// Signature of the withdrawal transaction (base58), once the note is spent
+    pub withdraw_tx: Option<String>,
+}
+
+impl NoteDisclosure {
+    /// Build a disclosure from a note, deriving its commitment and nullifier.
+    pub fn new(
+        pool_id: u8,
+        hash_function: HashFunction,
+        amount: u64,
+        secret: [u8; 32],
+        nullifier_preimage: [u8; 32],
+        deposit_tx: String,
+        withdraw_tx: Option<String>,
+    ) -> Result<Self> {
+        Ok(Self {
+            pool_id,
+            hash_function,
+            amount,
+            secret,
+            nullifier_preimage,
+            commitment: compute_commitment(hash_function, &secret, &nullifier_preimage)?,
+            nullifier_hash: compute_nullifier(hash_function, &nullifier_preimage)?,
+            deposit_tx,
+            withdraw_tx,
+        })
+    }
+
+    /// Check that the note opens `deposit` and `withdrawal`, the events the
+    /// auditor read from `deposit_tx` and `withdraw_tx` themselves, and is
+    /// worth `denomination`, read from the pool account.
+    pub fn verify(
+        &self,
+        denomination: u64,
+        deposit: &DepositEvent,
+        withdrawal: Option<&WithdrawEvent>,
+    ) -> Result<()> {
+        // 1. The claimed commitment and nullifier must come from the note
+        let commitment = compute_commitment(self.hash_function, &self.secret, &self.nullifier_preimage)?;
+        let nullifier = compute_nullifier(self.hash_function, &self.nullifier_preimage)?;
+        require!(
+            commitment == self.commitment && nullifier == self.nullifier_hash,
+            ClawLinkError::DisclosureMismatch
+        );
+
+        // 2. The deposit inserted this commitment into this pool, whose notes
+        //    are all worth its denomination
+        require!(
+            deposit.commitment == commitment
+                && deposit.pool_id == self.pool_id
+                && self.amount == denomination,
+            ClawLinkError::DisclosureMismatch
+        );
+
+        // 3. The withdrawal, if any, spent this nullifier
+        match (withdrawal, &self.withdraw_tx) {
+            (Some(withdrawal), Some(_)) => require!(
+                withdrawal.nullifier_hash == nullifier,
+                ClawLinkError::DisclosureMismatch
+            ),
+            (None, None) => {}
+            _ => return err!(ClawLinkError::DisclosureMismatch),
+        }
+        Ok(())
+    }
+}
+
+/// The note opening sealed in an audit memo, decrypted with the viewing key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuditOpening {
+    pub pool_id: u8,
+    pub secret: [u8; 32],
+    pub nullifier_preimage: [u8; 32],
+    /// Free-form bytes the depositor added, e.g. an invoice reference
+    pub memo: Vec<u8>,
+}
+
+impl AuditOpening {
+    /// Parse a decrypted audit memo.
+    pub fn from_plaintext(plaintext: &[u8]) -> Result<Self> {
+        require!(plaintext.len() >= 65, ClawLinkError::DisclosureMismatch);
+        let mut secret = [0u8; 32];
+        let mut nullifier_preimage = [0u8; 32];
+        secret.copy_from_slice(&plaintext[..32]);
+        nullifier_preimage.copy_from_slice(&plaintext[32..64]);
+        Ok(Self {
+            pool_id: plaintext[64],
+            secret,
+            nullifier_preimage,
+            memo: plaintext[65..].to_vec(),
+        })
+    }
+
+    /// Check that the opening matches `deposit`, the event that carried the
+    /// memo, and `withdrawal`, if the auditor found one spending its
+    /// nullifier. Nothing in the memo is taken on trust: the commitment and
+    /// nullifier are recomputed with the pool's hash function.
+    pub fn verify(
+        &self,
+        hash_function: HashFunction,
+        deposit: &DepositEvent,
+        withdrawal: Option<&WithdrawEvent>,
+    ) -> Result<()> {
+        let commitment =
+            compute_commitment(hash_function, &self.secret, &self.nullifier_preimage)?;
+        require!(
+            deposit.commitment == commitment && deposit.pool_id == self.pool_id,
+            ClawLinkError::DisclosureMismatch
+        );
+        if let Some(withdrawal) = withdrawal {
+            let nullifier = compute_nullifier(hash_function, &self.nullifier_preimage)?;
+            require!(
+                withdrawal.nullifier_hash == nullifier,
+                ClawLinkError::DisclosureMismatch
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POOL_ID: u8 = 0;
+    const DENOMINATION: u64 = 100_000_000;
+
+    fn disclosure(secret: [u8; 32], nullifier_preimage: [u8; 32]) -> NoteDisclosure {
+        NoteDisclosure::new(
+            POOL_ID,
+            HashFunction::Sha256,
+            DENOMINATION,
+            secret,
+            nullifier_preimage,
+            "deposit".to_string(),
+            Some("withdraw".to_string()),
+        )
+        .unwrap()
+    }
+
+    /// The events an honest deposit and withdrawal of the note emit.
+    fn events(note: &NoteDisclosure) -> (DepositEvent, WithdrawEvent) {
+        let deposit = DepositEvent {
+            commitment: note.commitment,
+            leaf_index: 7,
+            tree_index: 0,
+            pool_id: POOL_ID,
+            root: [9; 32],
+            timestamp: 0,
+            encrypted_note: None,
+            audit_memo: None,
+        };
+        let withdrawal = WithdrawEvent {
+            nullifier_hash: note.nullifier_hash,
+            recipient: Pubkey::new_unique(),
+            relayer: Pubkey::new_unique(),
+            fee: 0,
+        };
+        (deposit, withdrawal)
+    }
+
+    fn is_mismatch(result: Result<()>) -> bool {
+        result == Err(ClawLinkError::DisclosureMismatch.into())
+    }
+
+    #[test]
+    fn new_disclosure_verifies_against_its_events() {
+        let note = disclosure([1; 32], [2; 32]);
+        let (deposit, withdrawal) = events(&note);
+        assert!(note.verify(DENOMINATION, &deposit, Some(&withdrawal)).is_ok());
+
+        let unspent = NoteDisclosure { withdraw_tx: None, ..note };
+        assert!(unspent.verify(DENOMINATION, &deposit, None).is_ok());
+    }
+
+    #[test]
+    fn rejects_a_tampered_amount() {
+        let note = disclosure([1; 32], [2; 32]);
+        let (deposit, withdrawal) = events(&note);
+        let inflated = NoteDisclosure { amount: DENOMINATION * 10, ..note.clone() };
+        assert!(is_mismatch(inflated.verify(DENOMINATION, &deposit, Some(&withdrawal))));
+        // Nor does the note pass for one of another pool's denomination
+        assert!(is_mismatch(note.verify(DENOMINATION * 10, &deposit, Some(&withdrawal))));
+    }
+
+    #[test]
+    fn rejects_the_wrong_note_keys() {
+        let note = disclosure([1; 32], [2; 32]);
+        let (deposit, withdrawal) = events(&note);
+
+        // Someone else's secret and nullifier preimage open neither event
+        let other = disclosure([3; 32], [4; 32]);
+        assert!(is_mismatch(other.verify(DENOMINATION, &deposit, Some(&withdrawal))));
+
+        // Nor can the claimed commitment and nullifier be swapped in by hand
+        let forged = NoteDisclosure {
+            commitment: note.commitment,
+            nullifier_hash: note.nullifier_hash,
+            ..other
+        };
+        assert!(is_mismatch(forged.verify(DENOMINATION, &deposit, Some(&withdrawal))));
+    }
+
+    fn sealed_plaintext(note: &NoteDisclosure, memo: &[u8]) -> Vec<u8> {
+        [&note.secret[..], &note.nullifier_preimage[..], &[note.pool_id], memo].concat()
+    }
+
+    #[test]
+    fn audit_opening_verifies_against_its_events() {
+        let note = disclosure([1; 32], [2; 32]);
+        let (deposit, withdrawal) = events(&note);
+        let plaintext = sealed_plaintext(&note, b"invoice #42");
+        let opening = AuditOpening::from_plaintext(&plaintext).unwrap();
+        assert_eq!(opening.memo, b"invoice #42");
+        assert!(opening.verify(HashFunction::Sha256, &deposit, Some(&withdrawal)).is_ok());
+        assert!(opening.verify(HashFunction::Sha256, &deposit, None).is_ok());
+    }
+
+    #[test]
+    fn audit_opening_rejects_another_note_or_pool() {
+        let note = disclosure([1; 32], [2; 32]);
+        let (deposit, withdrawal) = events(&note);
+        let other_note = disclosure([3; 32], [4; 32]);
+        let (_, other_withdrawal) = events(&other_note);
+        let sha = HashFunction::Sha256;
+
+        let other = AuditOpening::from_plaintext(&sealed_plaintext(&other_note, b"")).unwrap();
+        assert!(is_mismatch(other.verify(sha, &deposit, None)));
+
+        let opening = AuditOpening::from_plaintext(&sealed_plaintext(&note, b"")).unwrap();
+        let elsewhere = AuditOpening { pool_id: POOL_ID + 1, ..opening.clone() };
+        assert!(is_mismatch(elsewhere.verify(sha, &deposit, Some(&withdrawal))));
+        assert!(is_mismatch(opening.verify(sha, &deposit, Some(&other_withdrawal))));
+
+        // A memo too short to hold an opening is no disclosure at all
+        assert!(AuditOpening::from_plaintext(b"invoice #42").is_err());
+    }
+
+    #[test]
+    fn rejects_an_unrelated_withdrawal() {
+        let note = disclosure([1; 32], [2; 32]);
+        let (deposit, _) = events(&note);
+        let (_, other_withdrawal) = events(&disclosure([3; 32], [4; 32]));
+        assert!(is_mismatch(note.verify(DENOMINATION, &deposit, Some(&other_withdrawal))));
+        assert!(is_mismatch(note.verify(DENOMINATION, &deposit, None)));
+    }
+}
@@ -0,0 +1,119 @@
+//! Groth16 proof verification over BN254 using Solana's alt_bn128 syscalls.
+//!
+//! Encoding follows EIP-197, which is what the syscalls expect:
+//!   G1 = x || y                       (2 × 32 bytes, big-endian)
+//!   G2 = x.c1 || x.c0 || y.c1 || y.c0 (4 × 32 bytes, big-endian)
+//!   Fr = 32 bytes big-endian, strictly below the scalar field modulus
+
+use anchor_lang::prelude::*;
+use solana_bn254::prelude::{alt_bn128_addition, alt_bn128_multiplication, alt_bn128_pairing};
+
+use crate::ClawLinkError;
+
+/// BN254 base field modulus q (big-endian), used to negate G1 points.
+const BASE_FIELD_MODULUS: [u8; 32] = [
+    0x30, 0x64, 0x4e, 0x72, 0xe1, 0x31, 0xa0, 0x29, 0xb8, 0x50, 0x45, 0xb6, 0x81, 0x81, 0x58, 0x5d,
+    0x97, 0x81, 0x6a, 0x91, 0x68, 0x71, 0xca, 0x8d, 0x3c, 0x20, 0x8c, 0x16, 0xd8, 0x7c, 0xfd, 0x47,
+];
+
+/// BN254 scalar field modulus r (big-endian). Public inputs must be below it.
+pub const SCALAR_FIELD_MODULUS: [u8; 32] = [
+    0x30, 0x64, 0x4e, 0x72, 0xe1, 0x31, 0xa0, 0x29, 0xb8, 0x50, 0x45, 0xb6, 0x81, 0x81, 0x58, 0x5d,
+    0x28, 0x33, 0xe8, 0x48, 0x79, 0xb9, 0x70, 0x91, 0x43, 0xe1, 0xf5, 0x93, 0xf0, 0x00, 0x00, 0x01,
+];
+
+/// A Groth16 proof in syscall encoding.
+pub struct Groth16Proof {
+    pub a: [u8; 64],
+    pub b: [u8; 128],
+    pub c: [u8; 64],
+}
+
+/// Borrowed view of a verifying key in syscall encoding.
+pub struct Groth16VerifyingKey<'a> {
+    pub alpha_g1: &'a [u8; 64],
+    pub beta_g2: &'a [u8; 128],
+    pub gamma_g2: &'a [u8; 128],
+    pub delta_g2: &'a [u8; 128],
+    /// `ic[0]` plus one G1 point per public input
+    pub ic: &'a [[u8; 64]],
+}
+
+/// Whether `value` is a canonical scalar field element (< r).
+pub fn is_field_element(value: &[u8; 32]) -> bool {
+    value < &SCALAR_FIELD_MODULUS
+}
+
+/// Verify `proof` against `vk` for the given public inputs.
+///
+/// Checks e(-A, B) · e(α, β) · e(Σ icᵢ·xᵢ, γ) · e(C, δ) == 1.
+pub fn verify(
+    vk: &Groth16VerifyingKey,
+    proof: &Groth16Proof,
+    public_inputs: &[[u8; 32]],
+) -> Result<()> {
+    require!(
+        vk.ic.len() == public_inputs.len() + 1,
+        ClawLinkError::InvalidVerifyingKey
+    );
+
+    // 1. Fold public inputs into a single G1 point
+    let mut prepared = vk.ic[0];
+    for (input, ic) in public_inputs.iter().zip(vk.ic[1..].iter()) {
+        require!(is_field_element(input), ClawLinkError::InvalidPublicInput);
+
+        let mut mul_input = [0u8; 96];
+        mul_input[..64].copy_from_slice(ic);
+        mul_input[64..].copy_from_slice(input);
+        let term = alt_bn128_multiplication(&mul_input)
+            .map_err(|_| error!(ClawLinkError::InvalidProof))?;
+
+        let mut add_input = [0u8; 128];
+        add_input[..64].copy_from_slice(&prepared);
+        add_input[64..].copy_from_slice(&term);
+        let sum = alt_bn128_addition(&add_input)
+            .map_err(|_| error!(ClawLinkError::InvalidProof))?;
+        prepared.copy_from_slice(&sum);
+    }
+
+    // 2. Pairing check over the four (G1, G2) pairs
+    let mut pairing_input = [0u8; 4 * 192];
+    let pairs: [(&[u8; 64], &[u8; 128]); 4] = [
+        (&negate_g1(&proof.a), &proof.b),
+        (vk.alpha_g1, vk.beta_g2),
+        (&prepared, vk.gamma_g2),
+        (&proof.c, vk.delta_g2),
+    ];
+    for (chunk, (g1, g2)) in pairing_input.chunks_exact_mut(192).zip(pairs.iter()) {
+        chunk[..64].copy_from_slice(*g1);
+        chunk[64..].copy_from_slice(*g2);
+    }
+    let result = alt_bn128_pairing(&pairing_input)
+        .map_err(|_| error!(ClawLinkError::InvalidProof))?;
+
+    let mut one = [0u8; 32];
+    one[31] = 1;
+    require!(result == one, ClawLinkError::InvalidProof);
+    Ok(())
+}
+
+/// Negate a G1 point: (x, y) → (x, q − y). The point at infinity is its own negation.
+fn negate_g1(point: &[u8; 64]) -> [u8; 64] {
+    let mut out = *point;
+    if point[32..].iter().all(|b| *b == 0) {
+        return out;
+    }
+
+    let mut borrow = 0i16;
+    for i in (0..32).rev() {
+        let diff = BASE_FIELD_MODULUS[i] as i16 - point[32 + i] as i16 - borrow;
+        if diff < 0 {
+            out[32 + i] = (diff + 256) as u8;
+            borrow = 1;
+        } else {
+            out[32 + i] = diff as u8;
+            borrow = 0;
+        }
+    }
+    out
+}
@@ -1,40 +1,132 @@
+// The `#[program]` macro still generates calls to the deprecated
+// `AccountInfo::realloc`
+#![allow(deprecated)]
+
 use anchor_lang::prelude::*;
 use anchor_lang::system_program;
-use anchor_spl::token::{self, Token, TokenAccount, Mint, Transfer as TokenTransfer};
+use anchor_spl::token::{
+    self, Burn, CloseAccount, Mint, Token, TokenAccount, Transfer as TokenTransfer,
+};
+use solana_curve25519::edwards::{validate_edwards, PodEdwardsPoint};
+use solana_poseidon::{Endianness, Parameters};
+use std::cmp::Ordering;
+
+#[cfg(not(target_os = "solana"))]
+pub mod disclosure;
+pub mod groth16;
 
 declare_id!("AV9QieTmdg2hFWsaZ3uTJRJuqqbhQCYFjn1fGiSYPTNe");
 
-/// ══════════════════════════════════════════════════════════════════════
-/// Claw Link Payments — Tornado Cash-style Private Payment Mixer
-/// ══════════════════════════════════════════════════════════════════════
-///
-/// Architecture:
-///   - Fixed denomination pools (0.1, 1, 10 SOL) for anonymity
-///   - Incremental Merkle tree of commitments per pool
-///   - Nullifier tracking to prevent double-spend
-///   - CLINK token fee gating on deposits
-///   - Simplified commitment/reveal (devnet) — swappable for ZK proofs
-///
-/// Privacy Model (devnet — simplified):
-///   commitment = SHA256(secret || nullifier_preimage)
-///   nullifier  = SHA256(nullifier_preimage)
-///   Depositor stores {secret, nullifier_preimage} off-chain as a "note"
-///   Withdrawer submits secret + nullifier_preimage, program verifies
-///
-/// ⚠️  PRODUCTION: Replace simplified verify with ZK proof verification.
-///     The program is designed with a clear boundary where groth16/ZERA
-///     proof verification would replace the hash-based scheme.
-/// ══════════════════════════════════════════════════════════════════════
+// ══════════════════════════════════════════════════════════════════════
+// Claw Link Payments — Tornado Cash-style Private Payment Mixer
+// ══════════════════════════════════════════════════════════════════════
+//
+// Architecture:
+//   - Fixed denomination pools for anonymity, listed in a pool registry
+//     (0.1, 1, 10 SOL by default; the authority can add any denomination)
+//   - Pools hold native SOL, or an SPL token in a vault-owned token account
+//   - Incremental Merkle tree of commitments per pool, depth set per pool;
+//     new pools hash leaves and nodes under distinct domain tags
+//   - Tree rollover: a full tree is archived (final root only) and the pool
+//     starts a fresh one, so a denomination never dies; nullifiers are
+//     shared by all of a pool's trees
+//   - Deposits either name their leaf index, or join a per-pool queue that a
+//     permissionless `flush_deposits` crank inserts into the tree, so
+//     concurrent depositors never race for the same index
+//   - Nullifier tracking to prevent double-spend, in 256 sorted shards keyed
+//     by the nullifier's last byte that grow 32 bytes per spend; pre-shard
+//     per-nullifier PDAs are still honoured and can be migrated
+//   - Optional per-pool minimum anonymity set and delay: a withdrawal must
+//     prove against a root old enough, and followed by enough deposits
+//   - Commitment markers: each commitment can be deposited once, in any pool
+//   - Shielded pools: notes carry hidden amounts; `transact` spends two
+//     notes into two new ones, with an optional public deposit/withdrawal,
+//     and a Groth16 proof enforces that amounts balance
+//   - Private note transfers: `transfer_note` spends a note and inserts a
+//     new commitment for the payee in the same pool, with no SOL moving
+//   - `DepositEvent` / `WithdrawEvent` emitted via self-CPI (`emit_cpi!`), so
+//     indexers can rebuild trees without reading logs or leaf accounts
+//   - Note delivery: a deposit can carry its note encrypted to the payee's
+//     X25519 key (the messaging `AgentProfile.encryption_key`); payees find
+//     incoming notes by trial-decrypting `DepositEvent`s
+//   - Selective disclosure: a deposit can carry an audit memo sealed to a
+//     viewing key of the depositor's choosing, and the `disclosure` module
+//     turns a note into a checkable link between its deposit and withdrawal
+//   - Per-deposit `CommitmentLeaf` accounts are optional per pool; in
+//     events-only pools anyone can close old leaf accounts, refunding the
+//     depositor's rent
+//   - Optional per-pool deposit limits per time window, overall and per
+//     depositor, counted in a `DepositWindow` account
+//   - Per-pool deposit/withdrawal counters, checked against the vault by
+//     the permissionless `audit_pool`
+//   - CLINK token fee gating on deposits (collected in the treasury, or
+//     burned when `burn_fees` is set)
+//   - Emergency pause: separate flags stop deposits and withdrawals; a
+//     guardian key can pause, only the authority can unpause
+//   - Two-step authority transfer (`propose_authority` / `accept_authority`);
+//     the authority can be an M-of-N `AuthoritySigners` set
+//   - Simplified commitment/reveal (devnet) — swappable for ZK proofs
+//
+// Privacy Model (devnet — simplified):
+//   commitment = H(secret || nullifier_preimage)
+//   nullifier  = H(nullifier_preimage)
+//   H is the pool's hash function: SHA256, or Poseidon (BN254) for pools
+//   whose tree has to match a SNARK circuit
+//   Depositor stores {secret, nullifier_preimage} off-chain as a "note"
+//   Withdrawer first commits binding = SHA256(secret || nullifier_preimage
+//   || recipient || relayer || fee), then in a later slot reveals secret +
+//   nullifier_preimage. The reveal only pays the recipient and relayer named
+//   in the earlier commit, so a copied reveal with a swapped recipient is
+//   useless to a front-runner. A relayer can submit both steps and take
+//   `fee` out of the denomination, so fresh recipients need no SOL.
+//
+// ⚠️  PRODUCTION: Replace simplified verify with ZK proof verification.
+//     The program is designed with a clear boundary where groth16/ZERA
+//     proof verification would replace the hash-based scheme.
+//     `withdraw_zk` is that path: a Groth16 proof over BN254, checked with
+//     the alt_bn128 syscalls against an authority-managed verifying key.
+// ══════════════════════════════════════════════════════════════════════
 
 // ─── Constants ──────────────────────────────────────────────────────
 
-/// Merkle tree depth: 20 levels → supports 2^20 = 1,048,576 deposits
+/// Maximum (and default) Merkle tree depth: 20 levels → 2^20 = 1,048,576 deposits
 pub const MERKLE_TREE_DEPTH: usize = 20;
 
-/// Maximum leaves in the Merkle tree
+/// Maximum leaves in the deepest Merkle tree
 pub const MAX_LEAVES: u32 = 1 << MERKLE_TREE_DEPTH; // 1,048,576
 
-/// Pool denominations in lamports
+/// Maximum number of pools the registry can list
+pub const MAX_POOLS: usize = 64;
+
+/// Number of recent Merkle roots a pool remembers for withdrawals
+pub const ROOT_HISTORY_SIZE: usize = 30;
+
+/// Size of a `Pool` account created before the root history and pool
+/// registry, which `migrate_pool` upgrades
+pub const LEGACY_POOL_SPACE: usize = 699;
+
+/// Size of a `CommitmentLeaf` account created before leaves recorded their
+/// payer and tree, which `close_legacy_leaf` closes
+pub const LEGACY_LEAF_SPACE: usize = 46;
+
+/// Maximum number of queued deposits waiting for `flush_deposits`
+pub const DEPOSIT_QUEUE_SIZE: usize = 16;
+
+/// Maximum ciphertext length of an encrypted note: secret and
+/// nullifier_preimage (64) plus the Poly1305 tag (16), with room for a memo
+pub const MAX_ENCRYPTED_NOTE_LEN: usize = 128;
+
+/// Byte of a nullifier that picks its shard, so there are 256 shards
+pub const NULLIFIER_SHARD_BYTE: usize = 31;
+
+/// Most depositors a `DepositWindow` counts separately within one window,
+/// when a per-depositor limit is on
+pub const MAX_WINDOW_DEPOSITORS: usize = 64;
+
+/// Maximum number of keys in an `AuthoritySigners` set
+pub const MAX_AUTHORITY_SIGNERS: usize = 10;
+
+/// Default pool denominations in lamports, registered as pools 0–2
 pub const POOL_DENOMINATIONS: [u64; 3] = [
     100_000_000,      // Pool 0: 0.1 SOL
     1_000_000_000,    // Pool 1: 1 SOL
@@ -44,44 +136,770 @@ pub const POOL_DENOMINATIONS: [u64; 3] = [
 /// Default CLINK fee per deposit (in token base units, 9 decimals)
 pub const DEFAULT_FEE: u64 = 10_000_000_000; // 10 CLINK (9 decimals)
 
-/// Zero value for empty Merkle tree leaves
+/// Default cap on relayer fees, in basis points of the pool denomination
+pub const DEFAULT_MAX_RELAYER_FEE_BPS: u16 = 500; // 5%
+
+/// Basis-point denominator
+pub const BPS_DENOMINATOR: u64 = 10_000;
+
+/// Number of Groth16 public inputs: root, nullifier_hash, recipient, relayer, fee
+pub const ZK_PUBLIC_INPUTS: usize = 5;
+
+/// Number of `transfer_note` public inputs: root, nullifier_hash, new_commitment
+pub const TRANSFER_PUBLIC_INPUTS: usize = 3;
+
+/// Notes spent and created by each `transact` in a shielded pool
+pub const TRANSACT_INPUTS: usize = 2;
+pub const TRANSACT_OUTPUTS: usize = 2;
+
+/// Number of `transact` public inputs: root, public_amount, ext_data_hash,
+/// two input nullifiers, two output commitments
+pub const TRANSACT_PUBLIC_INPUTS: usize = 3 + TRANSACT_INPUTS + TRANSACT_OUTPUTS;
+
+/// Zero value for empty leaves in legacy trees, and the "no root" sentinel
+/// in a pool's root history
 pub const ZERO_VALUE: [u8; 32] = [0u8; 32];
 
+/// Zero value for empty leaves in domain-separated trees:
+/// SHA256("claw-link") mod r, a nothing-up-my-sleeve BN254 field element
+pub const DOMAIN_ZERO_VALUE: [u8; 32] = [
+    0x17, 0x0e, 0x4d, 0x60, 0x27, 0x01, 0x7b, 0xf5, 0x4e, 0x0f, 0x9d, 0xe5, 0x38, 0xc7, 0x97, 0xb3,
+    0xa8, 0x5d, 0x20, 0x45, 0xd7, 0xcb, 0x36, 0x50, 0x90, 0x4e, 0xf0, 0xdf, 0xc7, 0xfb, 0x3e, 0xe0,
+];
+
+/// Tag hashed in front of a commitment to form a leaf (field element 1)
+pub const LEAF_DOMAIN: [u8; 32] = domain_tag(1);
+
+/// Tag hashed in front of two children to form an internal node (field element 2)
+pub const NODE_DOMAIN: [u8; 32] = domain_tag(2);
+
 // ─── Helpers ────────────────────────────────────────────────────────
 
+/// A 32-byte big-endian domain tag, valid as both a SHA256 prefix and a
+/// Poseidon input.
+const fn domain_tag(tag: u8) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    bytes[31] = tag;
+    bytes
+}
+
 /// Hash two 32-byte nodes together for the Merkle tree.
-fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
-    let mut data = [0u8; 64];
-    data[..32].copy_from_slice(left);
-    data[32..].copy_from_slice(right);
-    anchor_lang::solana_program::hash::hash(&data).to_bytes()
+fn hash_pair(
+    hash: HashFunction,
+    version: TreeVersion,
+    left: &[u8; 32],
+    right: &[u8; 32],
+) -> Result<[u8; 32]> {
+    match version {
+        TreeVersion::Legacy => hash.hashv(&[left, right]),
+        TreeVersion::DomainSeparated => hash.hashv(&[&NODE_DOMAIN, left, right]),
+    }
+}
+
+/// Turn a commitment into the leaf stored in the Merkle tree.
+fn hash_leaf(hash: HashFunction, version: TreeVersion, commitment: &[u8; 32]) -> Result<[u8; 32]> {
+    match version {
+        TreeVersion::Legacy => Ok(*commitment),
+        TreeVersion::DomainSeparated => hash.hashv(&[&LEAF_DOMAIN, commitment]),
+    }
 }
 
-/// Compute commitment = SHA256(secret || nullifier_preimage)
+/// Compute commitment = H(secret || nullifier_preimage)
 ///
-/// ⚠️  PRODUCTION ZK: Replace with Poseidon hash inside a circuit.
-fn compute_commitment(secret: &[u8; 32], nullifier_preimage: &[u8; 32]) -> [u8; 32] {
-    let mut data = [0u8; 64];
-    data[..32].copy_from_slice(secret);
-    data[32..].copy_from_slice(nullifier_preimage);
-    anchor_lang::solana_program::hash::hash(&data).to_bytes()
+/// For Poseidon pools both values must be BN254 field elements.
+fn compute_commitment(
+    hash: HashFunction,
+    secret: &[u8; 32],
+    nullifier_preimage: &[u8; 32],
+) -> Result<[u8; 32]> {
+    hash.hashv(&[secret, nullifier_preimage])
 }
 
-/// Compute nullifier = SHA256(nullifier_preimage)
+/// Compute nullifier = H(nullifier_preimage)
 ///
 /// ⚠️  PRODUCTION ZK: The nullifier would be derived inside the ZK circuit.
-fn compute_nullifier(nullifier_preimage: &[u8; 32]) -> [u8; 32] {
-    anchor_lang::solana_program::hash::hash(nullifier_preimage).to_bytes()
+fn compute_nullifier(hash: HashFunction, nullifier_preimage: &[u8; 32]) -> Result<[u8; 32]> {
+    hash.hashv(&[nullifier_preimage])
 }
 
-/// Precompute zero hashes for each level of the Merkle tree.
-fn zero_hashes() -> [[u8; 32]; MERKLE_TREE_DEPTH] {
+/// Precompute zero hashes for the first `depth` levels of the Merkle tree.
+fn zero_hashes(
+    hash: HashFunction,
+    version: TreeVersion,
+    depth: usize,
+) -> Result<[[u8; 32]; MERKLE_TREE_DEPTH]> {
+    let zero = version.zero_value();
     let mut zh = [[0u8; 32]; MERKLE_TREE_DEPTH];
-    zh[0] = hash_pair(&ZERO_VALUE, &ZERO_VALUE);
-    for i in 1..MERKLE_TREE_DEPTH {
-        zh[i] = hash_pair(&zh[i - 1], &zh[i - 1]);
+    zh[0] = hash_pair(hash, version, &zero, &zero)?;
+    for i in 1..depth {
+        zh[i] = hash_pair(hash, version, &zh[i - 1], &zh[i - 1])?;
+    }
+    Ok(zh)
+}
+
+/// Hashing version of tree `tree_index`: the pool's live tree, or an archived one.
+fn tree_version_of(
+    pool: &Pool,
+    archive: Option<&Account<TreeArchive>>,
+    tree_index: u32,
+) -> Result<TreeVersion> {
+    if tree_index == pool.tree_index {
+        return Ok(pool.tree_version);
+    }
+    let archive = archive.ok_or(ClawLinkError::MissingTreeArchive)?;
+    require!(archive.tree_index == tree_index, ClawLinkError::MissingTreeArchive);
+    Ok(archive.tree_version)
+}
+
+/// Whether `root` is accepted for tree `tree_index`: one of the live tree's
+/// recent roots, or the final root of an archived tree.
+fn is_accepted_root(
+    pool: &Pool,
+    archive: Option<&Account<TreeArchive>>,
+    tree_index: u32,
+    root: &[u8; 32],
+) -> Result<bool> {
+    if tree_index == pool.tree_index {
+        return Ok(pool.is_known_root(root));
+    }
+    let archive = archive.ok_or(ClawLinkError::MissingTreeArchive)?;
+    Ok(archive.tree_index == tree_index && archive.root == *root)
+}
+
+/// Enforce the pool's minimum anonymity set and delay for a withdrawal
+/// proving against `root` of tree `tree_index`. The root must be at least
+/// `min_withdraw_delay` seconds old and followed by at least
+/// `min_deposits_after` deposits; every leaf under it is then at least as
+/// old and as buried, without the withdrawal revealing which leaf it is.
+/// Live-tree roots come from the root history or the delay checkpoints.
+fn check_withdrawal_delay(
+    pool: &Pool,
+    archive: Option<&Account<TreeArchive>>,
+    tree_index: u32,
+    root: &[u8; 32],
+) -> Result<()> {
+    if pool.min_deposits_after == 0 && pool.min_withdraw_delay == 0 {
+        return Ok(());
+    }
+
+    let max_leaves = pool.max_leaves() as u64;
+    let (recorded_at, leaves_under_root) = if tree_index == pool.tree_index {
+        let (recorded_at, leaf_count) =
+            match pool.root_history.iter().position(|r| r == root) {
+                Some(slot) => (pool.root_timestamps[slot], pool.root_leaf_counts[slot]),
+                None => {
+                    let checkpoint = pool
+                        .delay_checkpoints
+                        .iter()
+                        .find(|c| c.root == *root)
+                        .ok_or(ClawLinkError::UnknownRoot)?;
+                    (checkpoint.recorded_at, checkpoint.leaf_count)
+                }
+            };
+        (recorded_at, tree_index as u64 * max_leaves + leaf_count as u64)
+    } else {
+        let archive = archive.ok_or(ClawLinkError::MissingTreeArchive)?;
+        (archive.root_recorded_at, (tree_index as u64 + 1) * max_leaves)
+    };
+
+    require!(
+        pool.total_leaves().saturating_sub(leaves_under_root) >= pool.min_deposits_after as u64,
+        ClawLinkError::AnonymitySetTooSmall
+    );
+    require!(
+        Clock::get()?.unix_timestamp.saturating_sub(recorded_at) >= pool.min_withdraw_delay as i64,
+        ClawLinkError::WithdrawalTooEarly
+    );
+    Ok(())
+}
+
+/// Compute the withdrawal binding =
+///   SHA256(secret || nullifier_preimage || recipient || relayer || fee_le)
+///
+/// Always SHA256: it is checked on-chain only and never enters a circuit.
+pub fn compute_withdrawal_binding(
+    secret: &[u8; 32],
+    nullifier_preimage: &[u8; 32],
+    recipient: &Pubkey,
+    relayer: &Pubkey,
+    fee: u64,
+) -> [u8; 32] {
+    anchor_lang::solana_program::hash::hashv(&[
+        secret,
+        nullifier_preimage,
+        recipient.as_ref(),
+        relayer.as_ref(),
+        &fee.to_le_bytes(),
+    ])
+    .to_bytes()
+}
+
+/// Check that `authority` speaks for `expected`: either it is `expected`, or
+/// `expected` is the `signer_set` account and at least `threshold` of its keys
+/// signed, counting `authority` and any signers among `co_signers`.
+fn check_authority(
+    expected: Pubkey,
+    signer_set: Option<&Account<AuthoritySigners>>,
+    authority: &Signer,
+    co_signers: &[AccountInfo],
+) -> Result<()> {
+    if authority.key() == expected {
+        return Ok(());
+    }
+    let set = signer_set
+        .filter(|set| set.key() == expected)
+        .ok_or(error!(ClawLinkError::Unauthorized))?;
+    let approvals = set
+        .signers
+        .iter()
+        .filter(|key| {
+            **key == authority.key()
+                || co_signers.iter().any(|info| info.is_signer && info.key == *key)
+        })
+        .count();
+    require!(approvals >= set.threshold as usize, ClawLinkError::NotEnoughSigners);
+    Ok(())
+}
+
+/// Largest relayer fee the config allows for a given denomination.
+fn max_relayer_fee(config: &ProtocolConfig, denomination: u64) -> u64 {
+    (denomination as u128 * config.max_relayer_fee_bps as u128 / BPS_DENOMINATOR as u128) as u64
+}
+
+/// Map a pubkey into the BN254 scalar field by clearing its top byte.
+fn pubkey_to_field(key: &Pubkey) -> [u8; 32] {
+    let mut out = key.to_bytes();
+    out[0] = 0;
+    out
+}
+
+/// Encode a u64 as a 32-byte big-endian field element.
+fn u64_to_field(value: u64) -> [u8; 32] {
+    let mut out = [0u8; 32];
+    out[24..].copy_from_slice(&value.to_be_bytes());
+    out
+}
+
+/// Encode a signed amount as a field element: `value` itself, or r - |value|
+/// when negative, which is how the circuit sees a withdrawal.
+pub fn signed_to_field(value: i128) -> [u8; 32] {
+    let magnitude = value.unsigned_abs().to_be_bytes();
+    let mut out = [0u8; 32];
+    out[16..].copy_from_slice(&magnitude);
+    if value >= 0 {
+        return out;
+    }
+
+    let mut borrow = 0i16;
+    for i in (0..32).rev() {
+        let diff = groth16::SCALAR_FIELD_MODULUS[i] as i16 - out[i] as i16 - borrow;
+        if diff < 0 {
+            out[i] = (diff + 256) as u8;
+            borrow = 1;
+        } else {
+            out[i] = diff as u8;
+            borrow = 0;
+        }
+    }
+    out
+}
+
+/// Compute a `transact`'s external data hash =
+///   SHA256(recipient || relayer || ext_amount_le || fee_le), top byte cleared
+///
+/// Binds the public side of the transaction into the proof, so a relayer
+/// can't redirect the withdrawal or raise its fee.
+pub fn compute_ext_data_hash(
+    recipient: &Pubkey,
+    relayer: &Pubkey,
+    ext_amount: i64,
+    fee: u64,
+) -> [u8; 32] {
+    let mut hash = anchor_lang::solana_program::hash::hashv(&[
+        recipient.as_ref(),
+        relayer.as_ref(),
+        &ext_amount.to_le_bytes(),
+        &fee.to_le_bytes(),
+    ])
+    .to_bytes();
+    hash[0] = 0;
+    hash
+}
+
+/// Take the CLINK deposit fee: burned, or sent to the treasury.
+fn charge_clink_fee<'info>(
+    config: &ProtocolConfig,
+    clink_mint: &Account<'info, Mint>,
+    depositor_clink: &Account<'info, TokenAccount>,
+    treasury: &Account<'info, TokenAccount>,
+    depositor: &Signer<'info>,
+    token_program: &Program<'info, Token>,
+) -> Result<()> {
+    let fee = config.fee_amount;
+    if fee > 0 && config.burn_fees {
+        let cpi_accounts = Burn {
+            mint: clink_mint.to_account_info(),
+            from: depositor_clink.to_account_info(),
+            authority: depositor.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(token_program.to_account_info(), cpi_accounts);
+        token::burn(cpi_ctx, fee)?;
+        msg!("CLINK fee of {} burned", fee);
+    } else if fee > 0 {
+        let cpi_accounts = TokenTransfer {
+            from: depositor_clink.to_account_info(),
+            to: treasury.to_account_info(),
+            authority: depositor.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(token_program.to_account_info(), cpi_accounts);
+        token::transfer(cpi_ctx, fee)?;
+        msg!("CLINK fee of {} collected", fee);
+    }
+    Ok(())
+}
+
+/// Accounts a deposit pays its CLINK fee and denomination from. The token
+/// accounts are only used (and required) for SPL-token pools.
+struct DepositAccounts<'a, 'info> {
+    config: &'a ProtocolConfig,
+    clink_mint: &'a Account<'info, Mint>,
+    depositor_clink: &'a Account<'info, TokenAccount>,
+    treasury: &'a Account<'info, TokenAccount>,
+    depositor: &'a Signer<'info>,
+    vault: &'a SystemAccount<'info>,
+    token_vault: Option<&'a Account<'info, TokenAccount>>,
+    depositor_token: Option<&'a Account<'info, TokenAccount>>,
+    system_program: &'a Program<'info, System>,
+    token_program: &'a Program<'info, Token>,
+}
+
+/// Take a deposit's CLINK fee (burned, or sent to the treasury) and move the
+/// denomination (SOL or pool token) into the pool's vault.
+fn collect_deposit(pool: &Pool, accounts: DepositAccounts) -> Result<()> {
+    // 1. Burn the CLINK fee, or transfer it to the treasury
+    charge_clink_fee(
+        accounts.config,
+        accounts.clink_mint,
+        accounts.depositor_clink,
+        accounts.treasury,
+        accounts.depositor,
+        accounts.token_program,
+    )?;
+
+    // 2. Transfer the denomination (SOL or pool token) into the vault
+    match pool.mint {
+        None => {
+            let cpi_ctx = CpiContext::new(
+                accounts.system_program.to_account_info(),
+                system_program::Transfer {
+                    from: accounts.depositor.to_account_info(),
+                    to: accounts.vault.to_account_info(),
+                },
+            );
+            system_program::transfer(cpi_ctx, pool.denomination)?;
+        }
+        Some(mint) => {
+            let (Some(token_vault), Some(depositor_token)) =
+                (accounts.token_vault, accounts.depositor_token)
+            else {
+                return err!(ClawLinkError::MissingTokenAccounts);
+            };
+            require!(depositor_token.mint == mint, ClawLinkError::InvalidMint);
+
+            let cpi_accounts = TokenTransfer {
+                from: depositor_token.to_account_info(),
+                to: token_vault.to_account_info(),
+                authority: accounts.depositor.to_account_info(),
+            };
+            let cpi_program = accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+            token::transfer(cpi_ctx, pool.denomination)?;
+        }
+    }
+    Ok(())
+}
+
+/// Accounts a withdrawal pays out through. The token accounts are only
+/// used (and required) for SPL-token pools.
+struct PayoutAccounts<'a, 'info> {
+    vault: &'a SystemAccount<'info>,
+    recipient: &'a UncheckedAccount<'info>,
+    relayer: &'a UncheckedAccount<'info>,
+    system_program: &'a Program<'info, System>,
+    token_vault: Option<&'a Account<'info, TokenAccount>>,
+    recipient_token: Option<&'a Account<'info, TokenAccount>>,
+    relayer_token: Option<&'a Account<'info, TokenAccount>>,
+    token_program: Option<&'a Program<'info, Token>>,
+}
+
+/// Pay a withdrawal out of the vault: `denomination - fee` to the recipient,
+/// `fee` to the relayer. SPL-token pools pay into token accounts owned by
+/// the recipient and relayer.
+fn pay_withdrawal(pool: &Pool, accounts: PayoutAccounts, fee: u64) -> Result<()> {
+    let denomination = pool.denomination;
+    let amount = denomination.checked_sub(fee).ok_or(ClawLinkError::Overflow)?;
+
+    let Some(mint) = pool.mint else {
+        require!(
+            vault_funds(accounts.vault)? >= denomination,
+            ClawLinkError::InsufficientVaultBalance
+        );
+        transfer_from_vault(
+            pool,
+            accounts.vault,
+            accounts.recipient.to_account_info(),
+            accounts.system_program,
+            amount,
+        )?;
+        if fee > 0 {
+            transfer_from_vault(
+                pool,
+                accounts.vault,
+                accounts.relayer.to_account_info(),
+                accounts.system_program,
+                fee,
+            )?;
+        }
+        return Ok(());
+    };
+
+    let (Some(token_vault), Some(recipient_token), Some(relayer_token), Some(token_program)) = (
+        accounts.token_vault,
+        accounts.recipient_token,
+        accounts.relayer_token,
+        accounts.token_program,
+    ) else {
+        return err!(ClawLinkError::MissingTokenAccounts);
+    };
+    require!(token_vault.amount >= denomination, ClawLinkError::InsufficientVaultBalance);
+    require!(
+        recipient_token.mint == mint && relayer_token.mint == mint,
+        ClawLinkError::InvalidMint
+    );
+    require!(
+        recipient_token.owner == accounts.recipient.key()
+            && relayer_token.owner == accounts.relayer.key(),
+        ClawLinkError::InvalidOwner
+    );
+
+    transfer_tokens_from_vault(
+        pool,
+        accounts.vault,
+        token_vault,
+        recipient_token,
+        token_program,
+        amount,
+    )?;
+    if fee > 0 {
+        transfer_tokens_from_vault(
+            pool,
+            accounts.vault,
+            token_vault,
+            relayer_token,
+            token_program,
+            fee,
+        )?;
+    }
+    Ok(())
+}
+
+/// Transfer tokens out of a pool's token vault; the vault PDA signs as its authority.
+fn transfer_tokens_from_vault<'info>(
+    pool: &Pool,
+    vault: &SystemAccount<'info>,
+    token_vault: &Account<'info, TokenAccount>,
+    to: &Account<'info, TokenAccount>,
+    token_program: &Program<'info, Token>,
+    amount: u64,
+) -> Result<()> {
+    let pool_id_bytes = pool.pool_id.to_le_bytes();
+    let vault_bump_bytes = [pool.vault_bump];
+    let signer_seeds: &[&[u8]] = &[b"vault", &pool_id_bytes, &vault_bump_bytes];
+    token::transfer(
+        CpiContext::new_with_signer(
+            token_program.to_account_info(),
+            TokenTransfer {
+                from: token_vault.to_account_info(),
+                to: to.to_account_info(),
+                authority: vault.to_account_info(),
+            },
+            &[signer_seeds],
+        ),
+        amount,
+    )
+}
+
+/// Transfer lamports out of a pool's vault PDA.
+fn transfer_from_vault<'info>(
+    pool: &Pool,
+    vault: &SystemAccount<'info>,
+    to: AccountInfo<'info>,
+    system_program: &Program<'info, System>,
+    amount: u64,
+) -> Result<()> {
+    let pool_id_bytes = pool.pool_id.to_le_bytes();
+    let vault_bump_bytes = [pool.vault_bump];
+    let signer_seeds: &[&[u8]] = &[b"vault", &pool_id_bytes, &vault_bump_bytes];
+    system_program::transfer(
+        CpiContext::new_with_signer(
+            system_program.to_account_info(),
+            system_program::Transfer {
+                from: vault.to_account_info(),
+                to,
+            },
+            &[signer_seeds],
+        ),
+        amount,
+    )
+}
+
+/// Lamports a SOL vault holds for its notes: everything above its rent-exempt
+/// minimum, which `initialize_pool` pre-funds and payouts must leave behind.
+fn vault_funds(vault: &SystemAccount) -> Result<u64> {
+    Ok(vault.lamports().saturating_sub(Rent::get()?.minimum_balance(0)))
+}
+
+/// Top up a SOL vault from `payer` to at least `target` lamports.
+fn fund_vault<'info>(
+    vault: &SystemAccount<'info>,
+    payer: &Signer<'info>,
+    system_program: &Program<'info, System>,
+    target: u64,
+) -> Result<()> {
+    let shortfall = target.saturating_sub(vault.lamports());
+    if shortfall > 0 {
+        system_program::transfer(
+            CpiContext::new(
+                system_program.to_account_info(),
+                system_program::Transfer {
+                    from: payer.to_account_info(),
+                    to: vault.to_account_info(),
+                },
+            ),
+            shortfall,
+        )?;
+    }
+    Ok(())
+}
+
+/// Claim the marker PDA `[b"commitment", commitment]` for a new deposit. The
+/// marker is an empty, program-owned account; finding it already claimed
+/// means the commitment was deposited before, and a second copy could never
+/// be withdrawn (both share one nullifier). Nullifier shards are shared by
+/// every pool, so markers are too: the copy could be in any pool.
+///
+/// Markers are permanent by design: no instruction closes one, not even
+/// `close_pool`, since a closed marker would let its commitment be deposited
+/// again. Each deposit therefore locks the rent of an empty account
+/// (890,880 lamports) for good, paid by the depositor.
+fn claim_commitment_marker<'info>(
+    marker: &UncheckedAccount<'info>,
+    depositor: &Signer<'info>,
+    system_program: &Program<'info, System>,
+    commitment: &[u8; 32],
+    bump: u8,
+) -> Result<()> {
+    require!(marker.owner != &crate::ID, ClawLinkError::DuplicateCommitment);
+
+    let bump_bytes = [bump];
+    let signer_seeds: &[&[u8]] = &[b"commitment", commitment, &bump_bytes];
+    let rent = Rent::get()?.minimum_balance(0);
+
+    // Top up rather than create, in case someone pre-funded the address
+    let shortfall = rent.saturating_sub(marker.lamports());
+    if shortfall > 0 {
+        system_program::transfer(
+            CpiContext::new(
+                system_program.to_account_info(),
+                system_program::Transfer {
+                    from: depositor.to_account_info(),
+                    to: marker.to_account_info(),
+                },
+            ),
+            shortfall,
+        )?;
+    }
+    system_program::assign(
+        CpiContext::new_with_signer(
+            system_program.to_account_info(),
+            system_program::Assign {
+                account_to_assign: marker.to_account_info(),
+            },
+            &[signer_seeds],
+        ),
+        &crate::ID,
+    )
+}
+
+/// Create the `CommitmentLeaf` PDA for a flushed deposit in the account
+/// passed for it, with `payer` covering the rent that `close_leaf` refunds.
+/// Like a commitment marker, the leaf is topped up rather than created, so a
+/// pre-funded address cannot block the flush.
+fn create_flushed_leaf<'info>(
+    leaf: &AccountInfo<'info>,
+    payer: &Signer<'info>,
+    system_program: &Program<'info, System>,
+    pool: &Pool,
+    leaf_index: u32,
+    commitment: [u8; 32],
+) -> Result<()> {
+    let pool_id_bytes = pool.pool_id.to_le_bytes();
+    let tree_index_bytes = pool.tree_index.to_le_bytes();
+    let leaf_index_bytes = leaf_index.to_le_bytes();
+    let (address, bump) = Pubkey::find_program_address(
+        &[b"leaf", &pool_id_bytes, &tree_index_bytes, &leaf_index_bytes],
+        &crate::ID,
+    );
+    require_keys_eq!(leaf.key(), address, ClawLinkError::LeafAccountMismatch);
+    require!(leaf.owner != &crate::ID, ClawLinkError::LeafAccountMismatch);
+
+    let space = 8 + CommitmentLeaf::INIT_SPACE;
+    let shortfall = Rent::get()?.minimum_balance(space).saturating_sub(leaf.lamports());
+    if shortfall > 0 {
+        system_program::transfer(
+            CpiContext::new(
+                system_program.to_account_info(),
+                system_program::Transfer {
+                    from: payer.to_account_info(),
+                    to: leaf.clone(),
+                },
+            ),
+            shortfall,
+        )?;
+    }
+    let bump_bytes = [bump];
+    let signer_seeds: &[&[u8]] =
+        &[b"leaf", &pool_id_bytes, &tree_index_bytes, &leaf_index_bytes, &bump_bytes];
+    system_program::allocate(
+        CpiContext::new_with_signer(
+            system_program.to_account_info(),
+            system_program::Allocate {
+                account_to_allocate: leaf.clone(),
+            },
+            &[signer_seeds],
+        ),
+        space as u64,
+    )?;
+    system_program::assign(
+        CpiContext::new_with_signer(
+            system_program.to_account_info(),
+            system_program::Assign {
+                account_to_assign: leaf.clone(),
+            },
+            &[signer_seeds],
+        ),
+        &crate::ID,
+    )?;
+
+    let record = CommitmentLeaf {
+        commitment,
+        leaf_index,
+        pool_id: pool.pool_id,
+        bump,
+        payer: payer.key(),
+        tree_index: pool.tree_index,
+    };
+    record.try_serialize(&mut &mut leaf.try_borrow_mut_data()?[..])
+}
+
+/// Check the optional payloads a deposit carries to its `DepositEvent`.
+fn check_payloads(
+    encrypted_note: &Option<EncryptedNote>,
+    audit_memo: &Option<EncryptedNote>,
+) -> Result<()> {
+    for payload in [encrypted_note, audit_memo].into_iter().flatten() {
+        require!(
+            payload.ciphertext.len() <= MAX_ENCRYPTED_NOTE_LEN,
+            ClawLinkError::EncryptedNoteTooLong
+        );
+    }
+    Ok(())
+}
+
+/// Shard a nullifier is stored in: its last byte, which is uniform for both
+/// SHA256 and (big-endian) BN254 field-element nullifiers.
+pub fn nullifier_shard(nullifier: &[u8; 32]) -> u8 {
+    nullifier[NULLIFIER_SHARD_BYTE]
+}
+
+/// Record a spent nullifier in its shard. A shard is a program-owned PDA
+/// holding its nullifiers as sorted 32-byte entries and nothing else; it is
+/// created on first use and grown by one entry per spend, with `payer`
+/// covering the extra rent. Fails if the nullifier is already in the shard.
+fn insert_nullifier<'info>(
+    shard: &UncheckedAccount<'info>,
+    payer: &Signer<'info>,
+    system_program: &Program<'info, System>,
+    nullifier: &[u8; 32],
+    bump: u8,
+) -> Result<()> {
+    if shard.owner != &crate::ID {
+        let shard_bytes = [nullifier_shard(nullifier)];
+        let bump_bytes = [bump];
+        let signer_seeds: &[&[u8]] = &[b"nullifier_shard", &shard_bytes, &bump_bytes];
+        system_program::assign(
+            CpiContext::new_with_signer(
+                system_program.to_account_info(),
+                system_program::Assign {
+                    account_to_assign: shard.to_account_info(),
+                },
+                &[signer_seeds],
+            ),
+            &crate::ID,
+        )?;
+    }
+
+    // 1. Find the nullifier's slot; an exact match is a double-spend
+    let len = shard.data_len();
+    let position = {
+        let data = shard.try_borrow_data()?;
+        let (mut low, mut high) = (0, len / 32);
+        while low < high {
+            let mid = (low + high) / 2;
+            match data[mid * 32..mid * 32 + 32].cmp(nullifier.as_slice()) {
+                Ordering::Less => low = mid + 1,
+                Ordering::Greater => high = mid,
+                Ordering::Equal => return err!(ClawLinkError::NullifierAlreadyUsed),
+            }
+        }
+        low * 32
+    };
+
+    // 2. Grow the shard by one entry, topping up its rent
+    let shortfall = Rent::get()?.minimum_balance(len + 32).saturating_sub(shard.lamports());
+    if shortfall > 0 {
+        system_program::transfer(
+            CpiContext::new(
+                system_program.to_account_info(),
+                system_program::Transfer {
+                    from: payer.to_account_info(),
+                    to: shard.to_account_info(),
+                },
+            ),
+            shortfall,
+        )?;
     }
-    zh
+    shard.resize(len + 32)?;
+
+    // 3. Shift the larger entries up and write the nullifier in its slot
+    let mut data = shard.try_borrow_mut_data()?;
+    data.copy_within(position..len, position + 32);
+    data[position..position + 32].copy_from_slice(nullifier);
+    Ok(())
+}
+
+/// Record a spent nullifier, after checking it has no pre-shard
+/// `NullifierAccount` (withdrawals before sharding, not yet migrated).
+fn spend_nullifier<'info>(
+    shard: &UncheckedAccount<'info>,
+    legacy: &UncheckedAccount<'info>,
+    payer: &Signer<'info>,
+    system_program: &Program<'info, System>,
+    nullifier: &[u8; 32],
+    bump: u8,
+) -> Result<()> {
+    require!(legacy.owner != &crate::ID, ClawLinkError::NullifierAlreadyUsed);
+    insert_nullifier(shard, payer, system_program, nullifier, bump)
 }
 
 // ─── Program ────────────────────────────────────────────────────────
@@ -102,430 +920,3102 @@ pub mod claw_link_payments {
         config.treasury = ctx.accounts.treasury.key();
         config.bump = ctx.bumps.config;
         config.treasury_bump = ctx.bumps.treasury;
+        config.max_relayer_fee_bps = DEFAULT_MAX_RELAYER_FEE_BPS;
+        config.guardian = ctx.accounts.authority.key();
         msg!("Claw Link Payments initialized. Fee: {} CLINK", fee_amount);
         Ok(())
     }
 
-    /// Initialize a denomination pool (0, 1, or 2).
+    /// Create the pool registry, listing the default pools 0–2 (authority only).
+    pub fn initialize_registry(ctx: Context<InitializeRegistry>) -> Result<()> {
+        check_authority(
+            ctx.accounts.config.authority,
+            ctx.accounts.authority_signers.as_deref(),
+            &ctx.accounts.authority,
+            ctx.remaining_accounts,
+        )?;
+        let registry = &mut ctx.accounts.registry;
+        registry.bump = ctx.bumps.registry;
+        registry.pools = POOL_DENOMINATIONS
+            .iter()
+            .enumerate()
+            .map(|(pool_id, denomination)| PoolEntry {
+                pool_id: pool_id as u8,
+                denomination: *denomination,
+                tree_depth: MERKLE_TREE_DEPTH as u8,
+                mint: None,
+                mode: PoolMode::Fixed,
+            })
+            .collect();
+
+        msg!("Pool registry initialized with {} pools", registry.pools.len());
+        Ok(())
+    }
+
+    /// Register a pool with any denomination and tree depth (authority only).
+    /// `mint` makes it an SPL-token pool denominated in that token's base
+    /// units; `None` means native SOL. The pool itself is then created with
+    /// `initialize_pool` (plus `initialize_token_vault` for token pools).
+    pub fn register_pool(
+        ctx: Context<RegisterPool>,
+        pool_id: u8,
+        denomination: u64,
+        tree_depth: u8,
+        mint: Option<Pubkey>,
+    ) -> Result<()> {
+        check_authority(
+            ctx.accounts.config.authority,
+            ctx.accounts.authority_signers.as_deref(),
+            &ctx.accounts.authority,
+            ctx.remaining_accounts,
+        )?;
+        require!(denomination > 0, ClawLinkError::InvalidDenomination);
+        require!(
+            tree_depth > 0 && tree_depth as usize <= MERKLE_TREE_DEPTH,
+            ClawLinkError::InvalidTreeDepth
+        );
+
+        let registry = &mut ctx.accounts.registry;
+        require!(registry.find(pool_id).is_none(), ClawLinkError::PoolAlreadyRegistered);
+        require!(registry.pools.len() < MAX_POOLS, ClawLinkError::RegistryFull);
+        registry.pools.push(PoolEntry {
+            pool_id,
+            denomination,
+            tree_depth,
+            mint,
+            mode: PoolMode::Fixed,
+        });
+
+        msg!(
+            "Pool {} registered: {} lamports denomination, depth {}",
+            pool_id, denomination, tree_depth
+        );
+        Ok(())
+    }
+
+    /// Register a shielded SOL pool (authority only). Its notes carry hidden
+    /// amounts and are only spent and created through `transact`, so it has
+    /// no denomination. Created with `initialize_pool` like any other pool.
+    pub fn register_shielded_pool(
+        ctx: Context<RegisterPool>,
+        pool_id: u8,
+        tree_depth: u8,
+    ) -> Result<()> {
+        check_authority(
+            ctx.accounts.config.authority,
+            ctx.accounts.authority_signers.as_deref(),
+            &ctx.accounts.authority,
+            ctx.remaining_accounts,
+        )?;
+        require!(
+            tree_depth > 0 && tree_depth as usize <= MERKLE_TREE_DEPTH,
+            ClawLinkError::InvalidTreeDepth
+        );
+
+        let registry = &mut ctx.accounts.registry;
+        require!(registry.find(pool_id).is_none(), ClawLinkError::PoolAlreadyRegistered);
+        require!(registry.pools.len() < MAX_POOLS, ClawLinkError::RegistryFull);
+        registry.pools.push(PoolEntry {
+            pool_id,
+            denomination: 0,
+            tree_depth,
+            mint: None,
+            mode: PoolMode::Shielded,
+        });
+
+        msg!("Shielded pool {} registered: depth {}", pool_id, tree_depth);
+        Ok(())
+    }
+
+    /// Initialize a registered pool with the given tree hash. Shielded pools
+    /// must use Poseidon, the only hash the `transact` circuit proves.
     pub fn initialize_pool(
         ctx: Context<InitializePool>,
         pool_id: u8,
+        hash_function: HashFunction,
     ) -> Result<()> {
-        require!(pool_id < 3, ClawLinkError::InvalidPool);
+        check_authority(
+            ctx.accounts.config.authority,
+            ctx.accounts.authority_signers.as_deref(),
+            &ctx.accounts.authority,
+            ctx.remaining_accounts,
+        )?;
+        let entry = *ctx.accounts.registry.find(pool_id).ok_or(ClawLinkError::InvalidPool)?;
+        require!(
+            entry.mode == PoolMode::Fixed || hash_function == HashFunction::Poseidon,
+            ClawLinkError::InvalidHashFunction
+        );
 
         let pool = &mut ctx.accounts.pool;
         pool.pool_id = pool_id;
-        pool.denomination = POOL_DENOMINATIONS[pool_id as usize];
+        pool.denomination = entry.denomination;
+        pool.tree_depth = entry.tree_depth;
+        pool.mint = entry.mint;
+        pool.mode = entry.mode;
         pool.next_index = 0;
+        pool.withdrawal_count = 0;
+        pool.sunset = false;
+        // Shielded pools never create leaf accounts; `transact` emits events only
+        pool.store_leaf_accounts = entry.mode == PoolMode::Fixed;
+        pool.tree_version = TreeVersion::DomainSeparated;
+        pool.tree_index = 0;
+        pool.min_deposits_after = 0;
+        pool.min_withdraw_delay = 0;
+        pool.total_deposits = 0;
+        pool.total_withdrawals = 0;
+        pool.shielded_balance = 0;
         pool.bump = ctx.bumps.pool;
         pool.vault_bump = ctx.bumps.vault;
+        pool.hash_function = hash_function;
+        pool.init_tree()?;
+
+        let queue = &mut ctx.accounts.deposit_queue;
+        queue.pool_id = pool_id;
+        queue.bump = ctx.bumps.deposit_queue;
+
+        // Deposit limits start off; see `update_deposit_limits`
+        let window = &mut ctx.accounts.deposit_window;
+        window.pool_id = pool_id;
+        window.bump = ctx.bumps.deposit_window;
 
-        // Initialize the filled_subtrees with zero hashes
-        let zh = zero_hashes();
-        pool.current_root = zh[MERKLE_TREE_DEPTH - 1];
-        pool.filled_subtrees = vec![ZERO_VALUE; MERKLE_TREE_DEPTH];
-        for i in 1..MERKLE_TREE_DEPTH {
-            pool.filled_subtrees[i] = zh[i - 1];
+        // A SOL vault holds its rent from the start, so no deposit or payout
+        // can leave it with a balance below the rent-exempt minimum
+        if entry.mint.is_none() {
+            fund_vault(
+                &ctx.accounts.vault,
+                &ctx.accounts.authority,
+                &ctx.accounts.system_program,
+                Rent::get()?.minimum_balance(0),
+            )?;
         }
 
-        msg!("Pool {} initialized: {} lamports denomination", pool_id, pool.denomination);
+        msg!(
+            "Pool {} initialized: {} lamports denomination, {:?} tree of depth {}",
+            pool_id, pool.denomination, hash_function, pool.tree_depth
+        );
+        Ok(())
+    }
+
+    /// Create the token vault for an SPL-token pool (authority only).
+    /// The vault PDA is the token account's authority.
+    pub fn initialize_token_vault(ctx: Context<InitializeTokenVault>, pool_id: u8) -> Result<()> {
+        check_authority(
+            ctx.accounts.config.authority,
+            ctx.accounts.authority_signers.as_deref(),
+            &ctx.accounts.authority,
+            ctx.remaining_accounts,
+        )?;
+        let pool = &mut ctx.accounts.pool;
+        require!(pool.mint == Some(ctx.accounts.mint.key()), ClawLinkError::InvalidMint);
+        pool.token_vault_bump = ctx.bumps.token_vault;
+
+        msg!("Token vault for pool {} initialized: mint {}", pool_id, ctx.accounts.mint.key());
         Ok(())
     }
 
-    /// Deposit SOL into a pool.
+    /// Deposit SOL (or the pool's SPL token) into a pool.
     /// Requires CLINK token fee and adds commitment to Merkle tree.
+    /// `encrypted_note` optionally carries the note for the payee, and
+    /// `audit_memo` a memo sealed to the depositor's viewing key; both are
+    /// only passed through to `DepositEvent`, never stored or checked.
     pub fn deposit(
         ctx: Context<DepositCtx>,
         commitment: [u8; 32],
         pool_id: u8,
         leaf_index: u32,
+        encrypted_note: Option<EncryptedNote>,
+        audit_memo: Option<EncryptedNote>,
     ) -> Result<()> {
-        require!(pool_id < 3, ClawLinkError::InvalidPool);
+        require!(!ctx.accounts.config.paused_deposits, ClawLinkError::DepositsPaused);
+        require!(ctx.accounts.registry.find(pool_id).is_some(), ClawLinkError::InvalidPool);
+        check_payloads(&encrypted_note, &audit_memo)?;
 
         let pool = &mut ctx.accounts.pool;
         let denomination = pool.denomination;
 
         require!(pool.pool_id == pool_id, ClawLinkError::InvalidPool);
-        require!(pool.next_index < MAX_LEAVES, ClawLinkError::MerkleTreeFull);
+        require!(pool.mode == PoolMode::Fixed, ClawLinkError::InvalidPoolMode);
+        require!(!pool.sunset, ClawLinkError::PoolSunset);
+        require!(
+            pool.next_index as usize + ctx.accounts.deposit_queue.pending.len()
+                < pool.max_leaves() as usize,
+            ClawLinkError::MerkleTreeFull
+        );
         require!(leaf_index == pool.next_index, ClawLinkError::InvalidProof);
+        if pool.hash_function == HashFunction::Poseidon {
+            require!(groth16::is_field_element(&commitment), ClawLinkError::InvalidFieldElement);
+        }
+        ctx.accounts
+            .deposit_window
+            .record(ctx.accounts.depositor.key(), Clock::get()?.unix_timestamp)?;
+        claim_commitment_marker(
+            &ctx.accounts.commitment_marker,
+            &ctx.accounts.depositor,
+            &ctx.accounts.system_program,
+            &commitment,
+            ctx.bumps.commitment_marker,
+        )?;
 
-        // 1. Transfer CLINK fee to treasury
-        let fee = ctx.accounts.config.fee_amount;
-        if fee > 0 {
-            let cpi_accounts = TokenTransfer {
-                from: ctx.accounts.depositor_clink.to_account_info(),
-                to: ctx.accounts.treasury.to_account_info(),
-                authority: ctx.accounts.depositor.to_account_info(),
-            };
-            let cpi_program = ctx.accounts.token_program.to_account_info();
-            let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
-            token::transfer(cpi_ctx, fee)?;
-            msg!("CLINK fee of {} collected", fee);
+        collect_deposit(
+            pool,
+            DepositAccounts {
+                config: &ctx.accounts.config,
+                clink_mint: &ctx.accounts.clink_mint,
+                depositor_clink: &ctx.accounts.depositor_clink,
+                treasury: &ctx.accounts.treasury,
+                depositor: &ctx.accounts.depositor,
+                vault: &ctx.accounts.vault,
+                token_vault: ctx.accounts.token_vault.as_deref(),
+                depositor_token: ctx.accounts.depositor_token.as_deref(),
+                system_program: &ctx.accounts.system_program,
+                token_program: &ctx.accounts.token_program,
+            },
+        )?;
+
+        // Insert commitment into incremental Merkle tree
+        let current_leaf_index = pool.insert_leaf(commitment)?;
+        pool.total_deposits = pool.total_deposits.checked_add(1).ok_or(ClawLinkError::Overflow)?;
+
+        // Store commitment in a leaf account, unless the pool is events-only
+        require!(
+            ctx.accounts.commitment_leaf.is_some() == pool.store_leaf_accounts,
+            ClawLinkError::LeafAccountMismatch
+        );
+        if let Some(leaf) = ctx.accounts.commitment_leaf.as_mut() {
+            leaf.commitment = commitment;
+            leaf.leaf_index = current_leaf_index;
+            leaf.pool_id = pool_id;
+            leaf.bump = ctx.bumps.commitment_leaf.ok_or(ClawLinkError::LeafAccountMismatch)?;
+            leaf.payer = ctx.accounts.depositor.key();
+            leaf.tree_index = pool.tree_index;
         }
 
-        // 2. Transfer SOL denomination to vault PDA
-        let cpi_ctx = CpiContext::new(
-            ctx.accounts.system_program.to_account_info(),
-            system_program::Transfer {
-                from: ctx.accounts.depositor.to_account_info(),
-                to: ctx.accounts.vault.to_account_info(),
+        emit_cpi!(DepositEvent {
+            commitment,
+            leaf_index: current_leaf_index,
+            tree_index: pool.tree_index,
+            pool_id,
+            root: pool.current_root,
+            timestamp: Clock::get()?.unix_timestamp,
+            encrypted_note,
+            audit_memo,
+        });
+
+        msg!(
+            "Deposited {} into pool {}. Leaf index: {}",
+            denomination, pool_id, current_leaf_index
+        );
+
+        Ok(())
+    }
+
+    /// Deposit without choosing a leaf index.
+    ///
+    /// Takes the same fee and denomination as `deposit`, but appends the
+    /// commitment to the pool's deposit queue; `flush_deposits` later assigns
+    /// it the next free leaf index. Concurrent depositors never collide.
+    /// `encrypted_note` and `audit_memo` wait in the queue with it and are
+    /// emitted in its `DepositEvent` on flush.
+    pub fn queue_deposit(
+        ctx: Context<QueueDepositCtx>,
+        commitment: [u8; 32],
+        pool_id: u8,
+        encrypted_note: Option<EncryptedNote>,
+        audit_memo: Option<EncryptedNote>,
+    ) -> Result<()> {
+        require!(!ctx.accounts.config.paused_deposits, ClawLinkError::DepositsPaused);
+        require!(ctx.accounts.registry.find(pool_id).is_some(), ClawLinkError::InvalidPool);
+        check_payloads(&encrypted_note, &audit_memo)?;
+
+        let pool = &ctx.accounts.pool;
+        let queue = &ctx.accounts.deposit_queue;
+
+        require!(pool.pool_id == pool_id, ClawLinkError::InvalidPool);
+        require!(pool.mode == PoolMode::Fixed, ClawLinkError::InvalidPoolMode);
+        require!(!pool.sunset, ClawLinkError::PoolSunset);
+        require!(queue.pending.len() < DEPOSIT_QUEUE_SIZE, ClawLinkError::DepositQueueFull);
+        require!(
+            pool.next_index as usize + queue.pending.len() < pool.max_leaves() as usize,
+            ClawLinkError::MerkleTreeFull
+        );
+        if pool.hash_function == HashFunction::Poseidon {
+            require!(groth16::is_field_element(&commitment), ClawLinkError::InvalidFieldElement);
+        }
+        ctx.accounts
+            .deposit_window
+            .record(ctx.accounts.depositor.key(), Clock::get()?.unix_timestamp)?;
+        claim_commitment_marker(
+            &ctx.accounts.commitment_marker,
+            &ctx.accounts.depositor,
+            &ctx.accounts.system_program,
+            &commitment,
+            ctx.bumps.commitment_marker,
+        )?;
+
+        collect_deposit(
+            pool,
+            DepositAccounts {
+                config: &ctx.accounts.config,
+                clink_mint: &ctx.accounts.clink_mint,
+                depositor_clink: &ctx.accounts.depositor_clink,
+                treasury: &ctx.accounts.treasury,
+                depositor: &ctx.accounts.depositor,
+                vault: &ctx.accounts.vault,
+                token_vault: ctx.accounts.token_vault.as_deref(),
+                depositor_token: ctx.accounts.depositor_token.as_deref(),
+                system_program: &ctx.accounts.system_program,
+                token_program: &ctx.accounts.token_program,
             },
+        )?;
+
+        let queue = &mut ctx.accounts.deposit_queue;
+        queue.pending.push(QueuedDeposit {
+            commitment,
+            encrypted_note,
+            audit_memo,
+        });
+
+        msg!(
+            "Queued deposit of {} into pool {}. Queue position: {}",
+            pool.denomination,
+            pool_id,
+            queue.pending.len() - 1
         );
-        system_program::transfer(cpi_ctx, denomination)?;
+        Ok(())
+    }
+
+    /// Insert up to `max_count` queued deposits into the pool's tree, oldest
+    /// first (permissionless). The new root is recorded once per flush, and
+    /// every flushed deposit's event carries that recorded root.
+    ///
+    /// If the pool stores leaf accounts, the remaining accounts are the
+    /// flushed deposits' `CommitmentLeaf` PDAs in leaf order; `payer` funds
+    /// their rent and gets it back from `close_leaf`.
+    pub fn flush_deposits<'info>(
+        ctx: Context<'_, '_, 'info, 'info, FlushDeposits<'info>>,
+        pool_id: u8,
+        max_count: u8,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        let queue = &mut ctx.accounts.deposit_queue;
 
-        // 3. Insert commitment into incremental Merkle tree
-        let current_leaf_index = pool.next_index;
-        let mut current_hash = commitment;
-        let mut current_index = current_leaf_index;
-        let zh = zero_hashes();
+        let count = queue.pending.len().min(max_count as usize);
+        require!(count > 0, ClawLinkError::DepositQueueEmpty);
+        let leaf_accounts = if pool.store_leaf_accounts { count } else { 0 };
+        require!(
+            ctx.remaining_accounts.len() == leaf_accounts,
+            ClawLinkError::LeafAccountMismatch
+        );
 
-        for i in 0..MERKLE_TREE_DEPTH {
-            if current_index % 2 == 0 {
-                pool.filled_subtrees[i] = current_hash;
-                let zero_at_level = if i == 0 { ZERO_VALUE } else { zh[i - 1] };
-                current_hash = hash_pair(&current_hash, &zero_at_level);
-            } else {
-                current_hash = hash_pair(&pool.filled_subtrees[i], &current_hash);
+        let first_index = pool.next_index;
+        let flushed: Vec<_> = queue.pending.drain(..count).collect();
+        for (i, queued) in flushed.iter().enumerate() {
+            let leaf_index = pool.append_leaf(queued.commitment)?;
+            if let Some(leaf) = ctx.remaining_accounts.get(i) {
+                create_flushed_leaf(
+                    leaf,
+                    &ctx.accounts.payer,
+                    &ctx.accounts.system_program,
+                    pool,
+                    leaf_index,
+                    queued.commitment,
+                )?;
             }
-            current_index /= 2;
         }
+        let root = pool.current_root;
+        pool.push_root(root)?;
 
-        pool.current_root = current_hash;
-        pool.next_index = current_leaf_index + 1;
-
-        // 4. Store commitment in leaf account
-        let leaf = &mut ctx.accounts.commitment_leaf;
-        leaf.commitment = commitment;
-        leaf.leaf_index = current_leaf_index;
-        leaf.pool_id = pool_id;
-        leaf.bump = ctx.bumps.commitment_leaf;
+        let timestamp = Clock::get()?.unix_timestamp;
+        for (leaf_index, queued) in (first_index..).zip(flushed) {
+            emit_cpi!(DepositEvent {
+                commitment: queued.commitment,
+                leaf_index,
+                tree_index: pool.tree_index,
+                pool_id,
+                root,
+                timestamp,
+                encrypted_note: queued.encrypted_note,
+                audit_memo: queued.audit_memo,
+            });
+        }
+        pool.total_deposits =
+            pool.total_deposits.checked_add(count as u64).ok_or(ClawLinkError::Overflow)?;
 
         msg!(
-            "Deposited {} lamports into pool {}. Leaf index: {}",
-            denomination, pool_id, current_leaf_index
+            "Flushed {} deposits into pool {}. Leaf indices: {}..{}",
+            count, pool_id, first_index, pool.next_index
         );
+        Ok(())
+    }
 
+    /// Commit to a withdrawal before revealing the note.
+    ///
+    /// `binding` = SHA256(secret || nullifier_preimage || recipient || relayer
+    /// || fee_le). It reveals nothing about the note; `withdraw` must be sent
+    /// in a later slot. Usually submitted by the relayer.
+    pub fn commit_withdrawal(ctx: Context<CommitWithdrawal>, binding: [u8; 32]) -> Result<()> {
+        let commit = &mut ctx.accounts.withdrawal_commit;
+        commit.binding = binding;
+        commit.slot = Clock::get()?.slot;
+        commit.bump = ctx.bumps.withdrawal_commit;
+
+        msg!("Withdrawal committed at slot {}", commit.slot);
         Ok(())
     }
 
     /// Withdraw SOL from a pool by revealing secret + nullifier_preimage.
+    /// Requires a `commit_withdrawal` for the same note, recipient, relayer
+    /// and fee from an earlier slot; the commit account is closed to the payer.
+    /// `denomination - fee` goes to the recipient and `fee` to the relayer.
+    /// `tree_index` names the pool tree holding the note; archived trees also
+    /// need their `tree_archive` account.
     ///
     /// ⚠️  PRODUCTION ZK: Replace hash verification with groth16 proof:
     ///     - Public inputs: root, nullifier, recipient, fee
     ///     - Private inputs: secret, nullifier_preimage, Merkle path
     ///     - ZK proves knowledge of a valid leaf without revealing which one
+    #[allow(clippy::too_many_arguments)]
     pub fn withdraw(
         ctx: Context<WithdrawCtx>,
         secret: [u8; 32],
         nullifier_preimage: [u8; 32],
         nullifier_hash: [u8; 32],
+        tree_index: u32,
         leaf_index: u32,
         proof: Vec<[u8; 32]>,
+        fee: u64,
     ) -> Result<()> {
-        let pool = &ctx.accounts.pool;
-
-        // 1. Verify nullifier_hash matches nullifier_preimage
-        let computed_nullifier = compute_nullifier(&nullifier_preimage);
+        require!(!ctx.accounts.config.paused_withdrawals, ClawLinkError::WithdrawalsPaused);
+        let pool = &mut ctx.accounts.pool;
+        let hash = pool.hash_function;
+        require!(ctx.accounts.registry.find(pool.pool_id).is_some(), ClawLinkError::InvalidPool);
+        require!(pool.mode == PoolMode::Fixed, ClawLinkError::InvalidPoolMode);
+        require!(
+            fee <= max_relayer_fee(&ctx.accounts.config, pool.denomination),
+            ClawLinkError::RelayerFeeTooHigh
+        );
+
+        // 1. Reveal must match an earlier commit naming this recipient and relayer
+        let commit = &ctx.accounts.withdrawal_commit;
+        let binding = compute_withdrawal_binding(
+            &secret,
+            &nullifier_preimage,
+            &ctx.accounts.recipient.key(),
+            &ctx.accounts.relayer.key(),
+            fee,
+        );
+        require!(commit.binding == binding, ClawLinkError::WithdrawalNotCommitted);
+        require!(commit.slot < Clock::get()?.slot, ClawLinkError::CommitTooRecent);
+
+        // 2. Verify nullifier_hash matches nullifier_preimage
+        let computed_nullifier = compute_nullifier(hash, &nullifier_preimage)?;
         require!(computed_nullifier == nullifier_hash, ClawLinkError::InvalidProof);
 
-        // 2. Compute and verify commitment
-        let commitment = compute_commitment(&secret, &nullifier_preimage);
+        // 3. Compute and verify commitment
+        let commitment = compute_commitment(hash, &secret, &nullifier_preimage)?;
+
+        // Verify Merkle proof against the tree the note was deposited into
+        let archive = ctx.accounts.tree_archive.as_deref();
+        let version = tree_version_of(pool, archive, tree_index)?;
+        let root = pool.compute_root(version, commitment, leaf_index, &proof)?;
+        require!(
+            is_accepted_root(pool, archive, tree_index, &root)?,
+            ClawLinkError::InvalidProof
+        );
+        check_withdrawal_delay(pool, archive, tree_index, &root)?;
+
+        // 4. Record nullifier (already recorded means a double-spend)
+        spend_nullifier(
+            &ctx.accounts.nullifier_shard,
+            &ctx.accounts.legacy_nullifier,
+            &ctx.accounts.payer,
+            &ctx.accounts.system_program,
+            &nullifier_hash,
+            ctx.bumps.nullifier_shard,
+        )?;
+        pool.withdrawal_count = pool.withdrawal_count.checked_add(1).ok_or(ClawLinkError::Overflow)?;
+        pool.total_withdrawals =
+            pool.total_withdrawals.checked_add(1).ok_or(ClawLinkError::Overflow)?;
+
+        // 5. Transfer SOL from vault to recipient and relayer via CPI with PDA signing
+        pay_withdrawal(
+            pool,
+            PayoutAccounts {
+                vault: &ctx.accounts.vault,
+                recipient: &ctx.accounts.recipient,
+                relayer: &ctx.accounts.relayer,
+                system_program: &ctx.accounts.system_program,
+                token_vault: ctx.accounts.token_vault.as_deref(),
+                recipient_token: ctx.accounts.recipient_token.as_deref(),
+                relayer_token: ctx.accounts.relayer_token.as_deref(),
+                token_program: ctx.accounts.token_program.as_ref(),
+            },
+            fee,
+        )?;
+
+        emit_cpi!(WithdrawEvent {
+            nullifier_hash,
+            recipient: ctx.accounts.recipient.key(),
+            relayer: ctx.accounts.relayer.key(),
+            fee,
+        });
+
+        msg!(
+            "Withdrawn {} lamports from pool {} to {} (relayer fee {})",
+            pool.denomination - fee,
+            pool.pool_id,
+            ctx.accounts.recipient.key(),
+            fee
+        );
+
+        Ok(())
+    }
+
+    /// Withdraw SOL from a Poseidon pool with a Groth16 proof.
+    ///
+    /// The proof attests knowledge of a note whose commitment is in the tree
+    /// under `root` and whose nullifier is `nullifier_hash`, without revealing
+    /// which leaf. Public inputs, in order:
+    ///   root, nullifier_hash, recipient, relayer, fee
+    /// Pubkeys enter the circuit with their top byte cleared; `fee` is a
+    /// big-endian u64. `denomination - fee` goes to the recipient and `fee`
+    /// to the relayer. `root` must belong to tree `tree_index`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn withdraw_zk(
+        ctx: Context<WithdrawZkCtx>,
+        proof_a: [u8; 64],
+        proof_b: [u8; 128],
+        proof_c: [u8; 64],
+        tree_index: u32,
+        root: [u8; 32],
+        nullifier_hash: [u8; 32],
+        fee: u64,
+    ) -> Result<()> {
+        require!(!ctx.accounts.config.paused_withdrawals, ClawLinkError::WithdrawalsPaused);
+        let pool = &mut ctx.accounts.pool;
+        require!(ctx.accounts.registry.find(pool.pool_id).is_some(), ClawLinkError::InvalidPool);
+        require!(pool.mode == PoolMode::Fixed, ClawLinkError::InvalidPoolMode);
+        // The circuit hashes with Poseidon; a SHA256 pool's roots mean nothing to it
+        require!(
+            pool.hash_function == HashFunction::Poseidon,
+            ClawLinkError::ProofNeedsPoseidonPool
+        );
+        require!(
+            fee <= max_relayer_fee(&ctx.accounts.config, pool.denomination),
+            ClawLinkError::RelayerFeeTooHigh
+        );
+
+        // 1. Root must be one the pool still accepts for that tree, and old enough
+        let archive = ctx.accounts.tree_archive.as_deref();
+        require!(
+            is_accepted_root(pool, archive, tree_index, &root)?,
+            ClawLinkError::UnknownRoot
+        );
+        check_withdrawal_delay(pool, archive, tree_index, &root)?;
+
+        // 2. Verify the Groth16 proof
+        let vk = &ctx.accounts.verifying_key;
+        let public_inputs: [[u8; 32]; ZK_PUBLIC_INPUTS] = [
+            root,
+            nullifier_hash,
+            pubkey_to_field(&ctx.accounts.recipient.key()),
+            pubkey_to_field(&ctx.accounts.relayer.key()),
+            u64_to_field(fee),
+        ];
+        groth16::verify(
+            &vk.as_groth16(),
+            &groth16::Groth16Proof {
+                a: proof_a,
+                b: proof_b,
+                c: proof_c,
+            },
+            &public_inputs,
+        )?;
+
+        // 3. Record nullifier (already recorded means a double-spend)
+        spend_nullifier(
+            &ctx.accounts.nullifier_shard,
+            &ctx.accounts.legacy_nullifier,
+            &ctx.accounts.payer,
+            &ctx.accounts.system_program,
+            &nullifier_hash,
+            ctx.bumps.nullifier_shard,
+        )?;
+        pool.withdrawal_count = pool.withdrawal_count.checked_add(1).ok_or(ClawLinkError::Overflow)?;
+        pool.total_withdrawals =
+            pool.total_withdrawals.checked_add(1).ok_or(ClawLinkError::Overflow)?;
+
+        // 4. Pay out recipient and relayer from the vault
+        pay_withdrawal(
+            pool,
+            PayoutAccounts {
+                vault: &ctx.accounts.vault,
+                recipient: &ctx.accounts.recipient,
+                relayer: &ctx.accounts.relayer,
+                system_program: &ctx.accounts.system_program,
+                token_vault: ctx.accounts.token_vault.as_deref(),
+                recipient_token: ctx.accounts.recipient_token.as_deref(),
+                relayer_token: ctx.accounts.relayer_token.as_deref(),
+                token_program: ctx.accounts.token_program.as_ref(),
+            },
+            fee,
+        )?;
+
+        emit_cpi!(WithdrawEvent {
+            nullifier_hash,
+            recipient: ctx.accounts.recipient.key(),
+            relayer: ctx.accounts.relayer.key(),
+            fee,
+        });
+
+        msg!(
+            "ZK withdrawn {} lamports from pool {} to {} (relayer fee {})",
+            pool.denomination - fee,
+            pool.pool_id,
+            ctx.accounts.recipient.key(),
+            fee
+        );
+
+        Ok(())
+    }
+
+    /// Pay a note to another agent without leaving the (Poseidon) pool.
+    ///
+    /// Spends the note behind `nullifier_hash` and inserts `new_commitment`,
+    /// built from the payee's secrets, into the same pool; no SOL moves, so
+    /// the payment stays inside the anonymity set and pays no CLINK fee.
+    /// The Groth16 proof shows the spent note is in tree `tree_index` under
+    /// `root`. Public inputs, in order:
+    ///   root, nullifier_hash, new_commitment
+    #[allow(clippy::too_many_arguments)]
+    pub fn transfer_note(
+        ctx: Context<TransferNoteCtx>,
+        proof_a: [u8; 64],
+        proof_b: [u8; 128],
+        proof_c: [u8; 64],
+        tree_index: u32,
+        root: [u8; 32],
+        nullifier_hash: [u8; 32],
+        new_commitment: [u8; 32],
+    ) -> Result<()> {
+        require!(!ctx.accounts.config.paused_withdrawals, ClawLinkError::WithdrawalsPaused);
+        let pool = &mut ctx.accounts.pool;
+        let pool_id = pool.pool_id;
+        require!(ctx.accounts.registry.find(pool_id).is_some(), ClawLinkError::InvalidPool);
+        require!(pool.mode == PoolMode::Fixed, ClawLinkError::InvalidPoolMode);
+        // As in `withdraw_zk`, the circuit only knows Poseidon trees
+        require!(
+            pool.hash_function == HashFunction::Poseidon,
+            ClawLinkError::ProofNeedsPoseidonPool
+        );
+        require!(!pool.sunset, ClawLinkError::PoolSunset);
+        require!(
+            pool.next_index as usize + ctx.accounts.deposit_queue.pending.len()
+                < pool.max_leaves() as usize,
+            ClawLinkError::MerkleTreeFull
+        );
+
+        // 1. Root must be one the pool still accepts for that tree, and old
+        //    enough: a transfer reveals the spend just as a withdrawal does
+        let archive = ctx.accounts.tree_archive.as_deref();
+        require!(
+            is_accepted_root(pool, archive, tree_index, &root)?,
+            ClawLinkError::UnknownRoot
+        );
+        check_withdrawal_delay(pool, archive, tree_index, &root)?;
+
+        // 2. Verify the Groth16 proof
+        let public_inputs: [[u8; 32]; TRANSFER_PUBLIC_INPUTS] =
+            [root, nullifier_hash, new_commitment];
+        groth16::verify(
+            &ctx.accounts.verifying_key.as_groth16(),
+            &groth16::Groth16Proof {
+                a: proof_a,
+                b: proof_b,
+                c: proof_c,
+            },
+            &public_inputs,
+        )?;
+
+        // 3. Record nullifier (already recorded means a double-spend)
+        spend_nullifier(
+            &ctx.accounts.nullifier_shard,
+            &ctx.accounts.legacy_nullifier,
+            &ctx.accounts.payer,
+            &ctx.accounts.system_program,
+            &nullifier_hash,
+            ctx.bumps.nullifier_shard,
+        )?;
+        pool.withdrawal_count = pool.withdrawal_count.checked_add(1).ok_or(ClawLinkError::Overflow)?;
+
+        // 4. Insert the payee's note, as a deposit would
+        claim_commitment_marker(
+            &ctx.accounts.commitment_marker,
+            &ctx.accounts.payer,
+            &ctx.accounts.system_program,
+            &new_commitment,
+            ctx.bumps.commitment_marker,
+        )?;
+        let leaf_index = pool.insert_leaf(new_commitment)?;
+
+        require!(
+            ctx.accounts.commitment_leaf.is_some() == pool.store_leaf_accounts,
+            ClawLinkError::LeafAccountMismatch
+        );
+        if let Some(leaf) = ctx.accounts.commitment_leaf.as_mut() {
+            leaf.commitment = new_commitment;
+            leaf.leaf_index = leaf_index;
+            leaf.pool_id = pool_id;
+            leaf.bump = ctx.bumps.commitment_leaf.ok_or(ClawLinkError::LeafAccountMismatch)?;
+            leaf.payer = ctx.accounts.payer.key();
+            leaf.tree_index = pool.tree_index;
+        }
+
+        emit_cpi!(DepositEvent {
+            commitment: new_commitment,
+            leaf_index,
+            tree_index: pool.tree_index,
+            pool_id,
+            root: pool.current_root,
+            timestamp: Clock::get()?.unix_timestamp,
+            encrypted_note: None,
+            audit_memo: None,
+        });
+        emit_cpi!(TransferEvent {
+            pool_id,
+            nullifier_hash,
+        });
+
+        msg!("Note transferred in pool {}. Leaf index: {}", pool_id, leaf_index);
+        Ok(())
+    }
+
+    /// Spend two notes of a shielded pool and create two new ones.
+    ///
+    /// The Groth16 proof shows both inputs are in the tree under
+    /// `proof.root` (or have zero amount), that their nullifiers are
+    /// `proof.input_nullifiers`, and that
+    ///   sum(inputs) + public_amount == sum(outputs)
+    /// without revealing any amount. Public inputs, in order:
+    ///   root, public_amount, ext_data_hash, input_nullifiers, output_commitments
+    /// where public_amount = ext_amount - fee (negative values wrap to r - |v|)
+    /// and ext_data_hash = `compute_ext_data_hash(recipient, relayer, ext_amount, fee)`.
+    /// A positive `ext_amount` is deposited by the payer, who also pays the
+    /// CLINK fee and is held to the pool's deposit limits; a negative one is
+    /// paid from the vault to the recipient. `fee` always goes to the relayer.
+    /// Unused inputs are zero-amount notes.
+    /// Withdrawals are subject to the pool's minimum anonymity set and delay.
+    pub fn transact(
+        ctx: Context<TransactCtx>,
+        tree_index: u32,
+        proof: TransactProof,
+        ext_amount: i64,
+        fee: u64,
+    ) -> Result<()> {
+        // Every transact spends notes; a deposit also brings funds in
+        let config = &ctx.accounts.config;
+        require!(!config.paused_withdrawals, ClawLinkError::WithdrawalsPaused);
+        require!(ext_amount <= 0 || !config.paused_deposits, ClawLinkError::DepositsPaused);
+        let pool = &mut ctx.accounts.pool;
+        require!(ctx.accounts.registry.find(pool.pool_id).is_some(), ClawLinkError::InvalidPool);
+        require!(pool.mode == PoolMode::Shielded, ClawLinkError::InvalidPoolMode);
+        require!(ext_amount <= 0 || !pool.sunset, ClawLinkError::PoolSunset);
+        require!(
+            pool.next_index as usize + TRANSACT_OUTPUTS <= pool.max_leaves() as usize,
+            ClawLinkError::MerkleTreeFull
+        );
+        if ext_amount > 0 {
+            ctx.accounts
+                .deposit_window
+                .record(ctx.accounts.payer.key(), Clock::get()?.unix_timestamp)?;
+        }
+
+        // 1. Root must be one the pool still accepts for that tree; withdrawals
+        //    also need it old enough
+        let archive = ctx.accounts.tree_archive.as_deref();
+        require!(
+            is_accepted_root(pool, archive, tree_index, &proof.root)?,
+            ClawLinkError::UnknownRoot
+        );
+        if ext_amount < 0 {
+            check_withdrawal_delay(pool, archive, tree_index, &proof.root)?;
+        }
+
+        // 2. Verify the join-split proof
+        let recipient = ctx.accounts.recipient.key();
+        let relayer = ctx.accounts.relayer.key();
+        let public_amount = signed_to_field(ext_amount as i128 - fee as i128);
+        let ext_data_hash = compute_ext_data_hash(&recipient, &relayer, ext_amount, fee);
+        let [nullifier_0, nullifier_1] = proof.input_nullifiers;
+        let [commitment_0, commitment_1] = proof.output_commitments;
+        let public_inputs: [[u8; 32]; TRANSACT_PUBLIC_INPUTS] = [
+            proof.root,
+            public_amount,
+            ext_data_hash,
+            nullifier_0,
+            nullifier_1,
+            commitment_0,
+            commitment_1,
+        ];
+        groth16::verify(
+            &ctx.accounts.verifying_key.as_groth16(),
+            &groth16::Groth16Proof {
+                a: proof.proof_a,
+                b: proof.proof_b,
+                c: proof.proof_c,
+            },
+            &public_inputs,
+        )?;
+
+        // 3. Record both nullifiers (already recorded means a double-spend)
+        spend_nullifier(
+            &ctx.accounts.nullifier_shard_0,
+            &ctx.accounts.legacy_nullifier_0,
+            &ctx.accounts.payer,
+            &ctx.accounts.system_program,
+            &nullifier_0,
+            ctx.bumps.nullifier_shard_0,
+        )?;
+        spend_nullifier(
+            &ctx.accounts.nullifier_shard_1,
+            &ctx.accounts.legacy_nullifier_1,
+            &ctx.accounts.payer,
+            &ctx.accounts.system_program,
+            &nullifier_1,
+            ctx.bumps.nullifier_shard_1,
+        )?;
+
+        // 4. Move the public amount into or out of the vault; the vault's
+        //    balance changes by ext_amount - fee either way
+        let balance = pool.shielded_balance as i128 + ext_amount as i128 - fee as i128;
+        require!(balance >= 0, ClawLinkError::InsufficientVaultBalance);
+        pool.shielded_balance = balance as u64;
+        if ext_amount > 0 {
+            pool.total_deposits =
+                pool.total_deposits.checked_add(1).ok_or(ClawLinkError::Overflow)?;
+        } else if ext_amount < 0 {
+            pool.total_withdrawals =
+                pool.total_withdrawals.checked_add(1).ok_or(ClawLinkError::Overflow)?;
+        }
+        if ext_amount > 0 {
+            let (Some(clink_mint), Some(payer_clink), Some(treasury), Some(token_program)) = (
+                ctx.accounts.clink_mint.as_deref(),
+                ctx.accounts.payer_clink.as_deref(),
+                ctx.accounts.treasury.as_deref(),
+                ctx.accounts.token_program.as_ref(),
+            ) else {
+                return err!(ClawLinkError::MissingFeeAccounts);
+            };
+            charge_clink_fee(
+                &ctx.accounts.config,
+                clink_mint,
+                payer_clink,
+                treasury,
+                &ctx.accounts.payer,
+                token_program,
+            )?;
+            system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    system_program::Transfer {
+                        from: ctx.accounts.payer.to_account_info(),
+                        to: ctx.accounts.vault.to_account_info(),
+                    },
+                ),
+                ext_amount as u64,
+            )?;
+        }
+        let withdrawal = if ext_amount < 0 { ext_amount.unsigned_abs() } else { 0 };
+        require!(
+            vault_funds(&ctx.accounts.vault)? >= withdrawal.checked_add(fee).ok_or(ClawLinkError::Overflow)?,
+            ClawLinkError::InsufficientVaultBalance
+        );
+        if withdrawal > 0 {
+            transfer_from_vault(
+                pool,
+                &ctx.accounts.vault,
+                ctx.accounts.recipient.to_account_info(),
+                &ctx.accounts.system_program,
+                withdrawal,
+            )?;
+        }
+        if fee > 0 {
+            transfer_from_vault(
+                pool,
+                &ctx.accounts.vault,
+                ctx.accounts.relayer.to_account_info(),
+                &ctx.accounts.system_program,
+                fee,
+            )?;
+        }
+
+        // 5. Append the output notes, each claiming its commitment marker as a
+        //    deposit would; the new root is recorded once and carried by both events
+        let pool_id = pool.pool_id;
+        let first_index = pool.next_index;
+        let markers = [
+            (&ctx.accounts.commitment_marker_0, ctx.bumps.commitment_marker_0),
+            (&ctx.accounts.commitment_marker_1, ctx.bumps.commitment_marker_1),
+        ];
+        for (commitment, (marker, bump)) in proof.output_commitments.iter().zip(markers) {
+            claim_commitment_marker(
+                marker,
+                &ctx.accounts.payer,
+                &ctx.accounts.system_program,
+                commitment,
+                bump,
+            )?;
+            pool.append_leaf(*commitment)?;
+        }
+        let root = pool.current_root;
+        pool.push_root(root)?;
+
+        let timestamp = Clock::get()?.unix_timestamp;
+        for (leaf_index, commitment) in (first_index..).zip(proof.output_commitments) {
+            emit_cpi!(DepositEvent {
+                commitment,
+                leaf_index,
+                tree_index: pool.tree_index,
+                pool_id,
+                root,
+                timestamp,
+                encrypted_note: None,
+                audit_memo: None,
+            });
+        }
+
+        emit_cpi!(TransactEvent {
+            pool_id,
+            input_nullifiers: proof.input_nullifiers,
+            ext_amount,
+            fee,
+            recipient,
+            relayer,
+        });
+
+        msg!(
+            "Transact in pool {}: external amount {}, relayer fee {}. Leaf indices: {}..{}",
+            pool_id,
+            ext_amount,
+            fee,
+            pool.next_index - TRANSACT_OUTPUTS as u32,
+            pool.next_index
+        );
+        Ok(())
+    }
+
+    /// Store the Groth16 verifying key for one circuit (authority only):
+    /// `Withdraw` for `withdraw_zk`, `Transfer` for `transfer_note`,
+    /// `Transact` for shielded pools.
+    pub fn initialize_verifying_key(
+        ctx: Context<InitializeVerifyingKey>,
+        circuit: ZkCircuit,
+        alpha_g1: [u8; 64],
+        beta_g2: [u8; 128],
+        gamma_g2: [u8; 128],
+        delta_g2: [u8; 128],
+        ic: Vec<[u8; 64]>,
+    ) -> Result<()> {
+        check_authority(
+            ctx.accounts.config.authority,
+            ctx.accounts.authority_signers.as_deref(),
+            &ctx.accounts.authority,
+            ctx.remaining_accounts,
+        )?;
+        require!(ic.len() == circuit.public_inputs() + 1, ClawLinkError::InvalidVerifyingKey);
+
+        let vk = &mut ctx.accounts.verifying_key;
+        vk.alpha_g1 = alpha_g1;
+        vk.beta_g2 = beta_g2;
+        vk.gamma_g2 = gamma_g2;
+        vk.delta_g2 = delta_g2;
+        vk.ic = ic;
+        vk.bump = ctx.bumps.verifying_key;
+
+        msg!(
+            "{:?} verifying key initialized by {}",
+            circuit,
+            ctx.accounts.authority.key()
+        );
+        Ok(())
+    }
+
+    /// Replace a circuit's Groth16 verifying key, e.g. after a new trusted setup (authority only).
+    pub fn update_verifying_key(
+        ctx: Context<UpdateVerifyingKey>,
+        circuit: ZkCircuit,
+        alpha_g1: [u8; 64],
+        beta_g2: [u8; 128],
+        gamma_g2: [u8; 128],
+        delta_g2: [u8; 128],
+        ic: Vec<[u8; 64]>,
+    ) -> Result<()> {
+        check_authority(
+            ctx.accounts.config.authority,
+            ctx.accounts.authority_signers.as_deref(),
+            &ctx.accounts.authority,
+            ctx.remaining_accounts,
+        )?;
+        require!(ic.len() == circuit.public_inputs() + 1, ClawLinkError::InvalidVerifyingKey);
+
+        let vk = &mut ctx.accounts.verifying_key;
+        vk.alpha_g1 = alpha_g1;
+        vk.beta_g2 = beta_g2;
+        vk.gamma_g2 = gamma_g2;
+        vk.delta_g2 = delta_g2;
+        vk.ic = ic;
+
+        msg!("{:?} verifying key updated by {}", circuit, ctx.accounts.authority.key());
+        Ok(())
+    }
+
+    /// Compare what a pool's vault holds with what its notes are owed, and
+    /// emit the result as a `PoolAuditEvent` (permissionless). Notes are owed
+    /// `outstanding * denomination`, counting queued deposits, or the
+    /// shielded balance. A SOL vault also holds its rent-exempt minimum on
+    /// top, pre-funded by `initialize_pool`.
+    pub fn audit_pool(ctx: Context<AuditPool>) -> Result<()> {
+        let pool = &ctx.accounts.pool;
+        let queued = ctx.accounts.deposit_queue.pending.len() as u64;
+        let outstanding_notes = pool.outstanding_notes() + queued;
+        let expected = match pool.mode {
+            PoolMode::Fixed => outstanding_notes
+                .checked_mul(pool.denomination)
+                .ok_or(ClawLinkError::Overflow)?,
+            PoolMode::Shielded => pool.shielded_balance,
+        };
+        let (held, allowance) = match pool.mint {
+            None => (ctx.accounts.vault.lamports(), Rent::get()?.minimum_balance(0)),
+            Some(_) => {
+                let token_vault = ctx
+                    .accounts
+                    .token_vault
+                    .as_ref()
+                    .ok_or(ClawLinkError::MissingTokenAccounts)?;
+                (token_vault.amount, 0)
+            }
+        };
+        let status = if held < expected {
+            AuditStatus::Insolvent
+        } else if held - expected > allowance {
+            AuditStatus::Surplus
+        } else {
+            AuditStatus::Balanced
+        };
+
+        emit_cpi!(PoolAuditEvent {
+            pool_id: pool.pool_id,
+            total_deposits: pool.total_deposits,
+            total_withdrawals: pool.total_withdrawals,
+            outstanding_notes,
+            expected,
+            held,
+            status,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+        msg!("Pool {} audit: {:?} (expected {}, held {})", pool.pool_id, status, expected, held);
+        Ok(())
+    }
+
+    /// Check whether a Merkle root is still accepted by a pool's withdrawals.
+    /// Intended for simulation (`.view()`) before building a withdrawal.
+    pub fn is_known_root(ctx: Context<PoolView>, root: [u8; 32]) -> Result<bool> {
+        Ok(ctx.accounts.pool.is_known_root(&root))
+    }
+
+    /// Update the CLINK fee amount (authority only).
+    pub fn update_fee(ctx: Context<UpdateConfig>, new_fee: u64) -> Result<()> {
+        check_authority(
+            ctx.accounts.config.authority,
+            ctx.accounts.authority_signers.as_deref(),
+            &ctx.accounts.authority,
+            ctx.remaining_accounts,
+        )?;
+        let config = &mut ctx.accounts.config;
+        config.fee_amount = new_fee;
+        msg!("Fee updated to {} CLINK", new_fee);
+        Ok(())
+    }
+
+    /// Update the maximum relayer fee, in basis points of the denomination (authority only).
+    pub fn update_max_relayer_fee(ctx: Context<UpdateConfig>, max_relayer_fee_bps: u16) -> Result<()> {
+        check_authority(
+            ctx.accounts.config.authority,
+            ctx.accounts.authority_signers.as_deref(),
+            &ctx.accounts.authority,
+            ctx.remaining_accounts,
+        )?;
+        require!(
+            max_relayer_fee_bps as u64 <= BPS_DENOMINATOR,
+            ClawLinkError::RelayerFeeTooHigh
+        );
+        let config = &mut ctx.accounts.config;
+        config.max_relayer_fee_bps = max_relayer_fee_bps;
+        msg!("Max relayer fee updated to {} bps", max_relayer_fee_bps);
+        Ok(())
+    }
+
+    /// Switch between burning deposit fees and collecting them in the treasury (authority only).
+    pub fn update_burn_fees(ctx: Context<UpdateConfig>, burn_fees: bool) -> Result<()> {
+        check_authority(
+            ctx.accounts.config.authority,
+            ctx.accounts.authority_signers.as_deref(),
+            &ctx.accounts.authority,
+            ctx.remaining_accounts,
+        )?;
+        let config = &mut ctx.accounts.config;
+        config.burn_fees = burn_fees;
+        msg!("Deposit fees will be {}", if burn_fees { "burned" } else { "collected" });
+        Ok(())
+    }
+
+    /// Set the guardian, the key that can pause (but not unpause) the program (authority only).
+    /// The guardian has to be able to pause alone, so it must be a wallet key:
+    /// PDAs, such as an `AuthoritySigners` set, are rejected.
+    pub fn update_guardian(ctx: Context<UpdateConfig>, guardian: Pubkey) -> Result<()> {
+        check_authority(
+            ctx.accounts.config.authority,
+            ctx.accounts.authority_signers.as_deref(),
+            &ctx.accounts.authority,
+            ctx.remaining_accounts,
+        )?;
+        require!(
+            validate_edwards(&PodEdwardsPoint(guardian.to_bytes())),
+            ClawLinkError::InvalidGuardian
+        );
+        let config = &mut ctx.accounts.config;
+        config.guardian = guardian;
+        msg!("Guardian updated to {}", guardian);
+        Ok(())
+    }
+
+    /// Stop deposits and/or withdrawals (guardian or authority).
+    /// Only sets flags; flags left false here keep their current value.
+    pub fn pause(ctx: Context<Pause>, deposits: bool, withdrawals: bool) -> Result<()> {
+        if ctx.accounts.signer.key() != ctx.accounts.config.guardian {
+            check_authority(
+                ctx.accounts.config.authority,
+                ctx.accounts.authority_signers.as_deref(),
+                &ctx.accounts.signer,
+                ctx.remaining_accounts,
+            )?;
+        }
+        let config = &mut ctx.accounts.config;
+        config.paused_deposits |= deposits;
+        config.paused_withdrawals |= withdrawals;
+        msg!(
+            "Paused deposits: {}, withdrawals: {}",
+            config.paused_deposits,
+            config.paused_withdrawals
+        );
+        Ok(())
+    }
+
+    /// Resume deposits and/or withdrawals (authority only).
+    pub fn unpause(ctx: Context<UpdateConfig>, deposits: bool, withdrawals: bool) -> Result<()> {
+        check_authority(
+            ctx.accounts.config.authority,
+            ctx.accounts.authority_signers.as_deref(),
+            &ctx.accounts.authority,
+            ctx.remaining_accounts,
+        )?;
+        let config = &mut ctx.accounts.config;
+        config.paused_deposits &= !deposits;
+        config.paused_withdrawals &= !withdrawals;
+        msg!(
+            "Paused deposits: {}, withdrawals: {}",
+            config.paused_deposits,
+            config.paused_withdrawals
+        );
+        Ok(())
+    }
+
+    /// Propose a new authority: a key, or an `AuthoritySigners` address (authority only).
+    /// Takes effect once the new authority calls `accept_authority`.
+    pub fn propose_authority(ctx: Context<UpdateConfig>, new_authority: Pubkey) -> Result<()> {
+        check_authority(
+            ctx.accounts.config.authority,
+            ctx.accounts.authority_signers.as_deref(),
+            &ctx.accounts.authority,
+            ctx.remaining_accounts,
+        )?;
+        let config = &mut ctx.accounts.config;
+        config.pending_authority = Some(new_authority);
+        msg!("Proposed authority {}", new_authority);
+        Ok(())
+    }
+
+    /// Accept a proposed authority transfer (the proposed authority only).
+    /// The guardian is left as it is; the new authority can replace it with
+    /// `update_guardian`.
+    pub fn accept_authority(ctx: Context<AcceptAuthority>) -> Result<()> {
+        let pending = ctx
+            .accounts
+            .config
+            .pending_authority
+            .ok_or(ClawLinkError::NoPendingAuthority)?;
+        check_authority(
+            pending,
+            ctx.accounts.authority_signers.as_deref(),
+            &ctx.accounts.authority,
+            ctx.remaining_accounts,
+        )?;
+        let config = &mut ctx.accounts.config;
+        config.authority = pending;
+        config.pending_authority = None;
+        msg!("Authority transferred to {}", pending);
+        Ok(())
+    }
+
+    /// Create an M-of-N signer set that the authority can later be handed to
+    /// with `propose_authority`. Sets are immutable; to change one, create a
+    /// new set and transfer the authority to it.
+    pub fn create_authority_signers(
+        ctx: Context<CreateAuthoritySigners>,
+        _set_id: u8,
+        threshold: u8,
+        signers: Vec<Pubkey>,
+    ) -> Result<()> {
+        require!(
+            threshold > 0
+                && threshold as usize <= signers.len()
+                && signers.len() <= MAX_AUTHORITY_SIGNERS,
+            ClawLinkError::InvalidSignerSet
+        );
+        for (i, key) in signers.iter().enumerate() {
+            require!(!signers[..i].contains(key), ClawLinkError::InvalidSignerSet);
+        }
+
+        let set = &mut ctx.accounts.authority_signers;
+        set.threshold = threshold;
+        set.signers = signers;
+        set.bump = ctx.bumps.authority_signers;
+        msg!("Signer set {}: {} of {}", set.key(), threshold, set.signers.len());
+        Ok(())
+    }
+
+    /// Move collected CLINK fees out of the treasury (authority only).
+    /// The config PDA signs as the treasury's token authority.
+    pub fn withdraw_treasury(ctx: Context<WithdrawTreasury>, amount: u64) -> Result<()> {
+        check_authority(
+            ctx.accounts.config.authority,
+            ctx.accounts.authority_signers.as_deref(),
+            &ctx.accounts.authority,
+            ctx.remaining_accounts,
+        )?;
+        require!(
+            ctx.accounts.treasury.amount >= amount,
+            ClawLinkError::InsufficientTreasuryBalance
+        );
+
+        let config_bump = [ctx.accounts.config.bump];
+        let signer_seeds: &[&[u8]] = &[b"config", &config_bump];
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TokenTransfer {
+                    from: ctx.accounts.treasury.to_account_info(),
+                    to: ctx.accounts.destination.to_account_info(),
+                    authority: ctx.accounts.config.to_account_info(),
+                },
+                &[signer_seeds],
+            ),
+            amount,
+        )?;
+
+        msg!(
+            "Withdrew {} CLINK from treasury to {}",
+            amount,
+            ctx.accounts.destination.key()
+        );
+        Ok(())
+    }
+
+    /// Close the config PDA and the (empty) treasury, reclaiming rent (authority only).
+    /// Used when resetting protocol state for redeployment; drain the
+    /// treasury with `withdraw_treasury` first.
+    pub fn close_config(ctx: Context<CloseConfig>) -> Result<()> {
+        check_authority(
+            ctx.accounts.config.authority,
+            ctx.accounts.authority_signers.as_deref(),
+            &ctx.accounts.authority,
+            ctx.remaining_accounts,
+        )?;
+        require!(ctx.accounts.treasury.amount == 0, ClawLinkError::TreasuryNotEmpty);
+
+        let config_bump = [ctx.accounts.config.bump];
+        let signer_seeds: &[&[u8]] = &[b"config", &config_bump];
+        token::close_account(CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            CloseAccount {
+                account: ctx.accounts.treasury.to_account_info(),
+                destination: ctx.accounts.authority.to_account_info(),
+                authority: ctx.accounts.config.to_account_info(),
+            },
+            &[signer_seeds],
+        ))?;
+
+        msg!("Config PDA closed by authority {}", ctx.accounts.authority.key());
+        Ok(())
+    }
+
+    /// Archive a full tree and start a fresh one (permissionless).
+    ///
+    /// Only the full tree's final root is kept: it covers every leaf, so its
+    /// notes stay withdrawable by passing its `tree_index` (proofs against an
+    /// older root of that tree must be rebuilt against the final one).
+    /// Nullifiers are per note, not per tree, so nothing can be spent twice.
+    /// The new tree always uses the current hashing version. A shielded pool
+    /// counts as full once a `transact`'s two outputs no longer fit.
+    pub fn rollover_tree(ctx: Context<RolloverTree>, pool_id: u8) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        require!(pool.is_full(), ClawLinkError::TreeNotFull);
+
+        let archive = &mut ctx.accounts.tree_archive;
+        archive.pool_id = pool_id;
+        archive.tree_index = pool.tree_index;
+        archive.root = pool.current_root;
+        archive.root_recorded_at = pool.root_timestamps[pool.current_root_index as usize];
+        archive.tree_version = pool.tree_version;
+        archive.bump = ctx.bumps.tree_archive;
+
+        pool.tree_index = pool.tree_index.checked_add(1).ok_or(ClawLinkError::Overflow)?;
+        pool.tree_version = TreeVersion::DomainSeparated;
+        pool.next_index = 0;
+        pool.init_tree()?;
+
+        msg!("Pool {} rolled over to tree {}", pool_id, pool.tree_index);
+        Ok(())
+    }
+
+    /// Stop new deposits into a pool while keeping withdrawals open (authority only).
+    /// Once every note is withdrawn the pool can be closed with `close_pool`.
+    pub fn sunset_pool(ctx: Context<UpdatePool>, pool_id: u8) -> Result<()> {
+        check_authority(
+            ctx.accounts.config.authority,
+            ctx.accounts.authority_signers.as_deref(),
+            &ctx.accounts.authority,
+            ctx.remaining_accounts,
+        )?;
+        let pool = &mut ctx.accounts.pool;
+        pool.sunset = true;
+        msg!(
+            "Pool {} sunset with {} outstanding notes",
+            pool_id,
+            pool.total_leaves() - pool.withdrawal_count as u64
+        );
+        Ok(())
+    }
+
+    /// Choose whether `deposit` creates a `CommitmentLeaf` account per deposit
+    /// (authority only). Events-only pools rely on `DepositEvent` history for
+    /// proof generation, and their existing leaf accounts can be closed.
+    pub fn update_leaf_storage(
+        ctx: Context<UpdatePool>,
+        pool_id: u8,
+        store_leaf_accounts: bool,
+    ) -> Result<()> {
+        check_authority(
+            ctx.accounts.config.authority,
+            ctx.accounts.authority_signers.as_deref(),
+            &ctx.accounts.authority,
+            ctx.remaining_accounts,
+        )?;
+        ctx.accounts.pool.store_leaf_accounts = store_leaf_accounts;
+        msg!("Pool {} leaf accounts: {}", pool_id, store_leaf_accounts);
+        Ok(())
+    }
+
+    /// Set the pool's minimum anonymity set and delay for withdrawals and
+    /// note transfers (authority only); zero disables either.
+    ///
+    /// Each deposit records a root and only the last `ROOT_HISTORY_SIZE` are
+    /// kept, so `min_deposits_after` must stay below `ROOT_HISTORY_SIZE`.
+    /// The delay does not depend on the history: the pool's delay checkpoints
+    /// keep a root old enough however many deposits arrive (see
+    /// `Pool::delay_checkpoints`). They restart at the current root here.
+    pub fn update_withdrawal_delay(
+        ctx: Context<UpdatePool>,
+        pool_id: u8,
+        min_deposits_after: u32,
+        min_withdraw_delay: u32,
+    ) -> Result<()> {
+        check_authority(
+            ctx.accounts.config.authority,
+            ctx.accounts.authority_signers.as_deref(),
+            &ctx.accounts.authority,
+            ctx.remaining_accounts,
+        )?;
+        require!(
+            (min_deposits_after as usize) < ROOT_HISTORY_SIZE,
+            ClawLinkError::InvalidWithdrawalDelay
+        );
+        let pool = &mut ctx.accounts.pool;
+        pool.min_deposits_after = min_deposits_after;
+        pool.min_withdraw_delay = min_withdraw_delay;
+        pool.reset_checkpoints();
+        msg!(
+            "Pool {} withdrawals need {} later deposits and {}s delay",
+            pool_id, min_deposits_after, min_withdraw_delay
+        );
+        Ok(())
+    }
+
+    /// Set a pool's deposit limits (authority only): at most `max_deposits`
+    /// per `window_seconds`, and `max_per_depositor` from any one depositor
+    /// (0 = no limit). Windows are fixed, so up to twice `max_deposits` can
+    /// land around a window boundary. Deposits counted so far are forgotten.
+    pub fn update_deposit_limits(
+        ctx: Context<UpdateDepositLimits>,
+        pool_id: u8,
+        window_seconds: u32,
+        max_deposits: u32,
+        max_per_depositor: u16,
+    ) -> Result<()> {
+        check_authority(
+            ctx.accounts.config.authority,
+            ctx.accounts.authority_signers.as_deref(),
+            &ctx.accounts.authority,
+            ctx.remaining_accounts,
+        )?;
+        let valid = if window_seconds == 0 {
+            max_deposits == 0 && max_per_depositor == 0
+        } else {
+            max_deposits > 0
+        };
+        require!(valid, ClawLinkError::InvalidDepositLimits);
+
+        let window = &mut ctx.accounts.deposit_window;
+        window.window_seconds = window_seconds;
+        window.max_deposits = max_deposits;
+        window.max_per_depositor = max_per_depositor;
+        window.window_start = 0;
+        window.count = 0;
+        window.depositors.clear();
+        msg!(
+            "Pool {} deposits limited to {} per {}s, {} per depositor",
+            pool_id, max_deposits, window_seconds, max_per_depositor
+        );
+        Ok(())
+    }
+
+    /// Close a `CommitmentLeaf` account and refund its rent to the depositor
+    /// (permissionless). Only allowed once the pool is events-only or sunset,
+    /// since the leaf is then recoverable from `DepositEvent` history alone.
+    pub fn close_leaf(
+        ctx: Context<CloseLeaf>,
+        pool_id: u8,
+        tree_index: u32,
+        leaf_index: u32,
+    ) -> Result<()> {
+        let pool = &ctx.accounts.pool;
+        require!(
+            !pool.store_leaf_accounts || pool.sunset,
+            ClawLinkError::LeafStillRequired
+        );
+        msg!("Leaf {} of pool {} tree {} closed", leaf_index, pool_id, tree_index);
+        Ok(())
+    }
+
+    /// Close a `CommitmentLeaf` account from before leaves recorded their
+    /// payer and tree (permissionless). Such leaves sit at
+    /// `[b"leaf", pool_id, leaf_index]` in the pool's first tree. Allowed
+    /// under the same conditions as `close_leaf`.
+    ///
+    /// The protocol reclaims this rent: it goes to the protocol authority,
+    /// not the depositor who paid it, since nothing on-chain records who
+    /// that was.
+    pub fn close_legacy_leaf(
+        ctx: Context<CloseLegacyLeaf>,
+        pool_id: u8,
+        leaf_index: u32,
+    ) -> Result<()> {
+        let pool = &ctx.accounts.pool;
+        require!(
+            !pool.store_leaf_accounts || pool.sunset,
+            ClawLinkError::LeafStillRequired
+        );
+        let leaf = ctx.accounts.legacy_leaf.to_account_info();
+        {
+            let data = leaf.try_borrow_data()?;
+            require!(
+                data.len() == LEGACY_LEAF_SPACE
+                    && data.starts_with(CommitmentLeaf::DISCRIMINATOR),
+                ClawLinkError::NotLegacyAccount
+            );
+        }
+
+        let authority = ctx.accounts.authority.to_account_info();
+        let refund = authority
+            .lamports()
+            .checked_add(leaf.lamports())
+            .ok_or(ClawLinkError::Overflow)?;
+        **authority.try_borrow_mut_lamports()? = refund;
+        **leaf.try_borrow_mut_lamports()? = 0;
+        leaf.assign(&system_program::ID);
+        leaf.resize(0)?;
+
+        msg!(
+            "Legacy leaf {} of pool {} closed, rent reclaimed by the protocol",
+            leaf_index, pool_id
+        );
+        Ok(())
+    }
+
+    /// Move a pre-shard `NullifierAccount` into its nullifier shard and close
+    /// it (permissionless). The caller pays for the shard's extra entry and
+    /// keeps the old account's larger rent, so anyone can crank migrations.
+    pub fn migrate_nullifier(ctx: Context<MigrateNullifier>, nullifier_hash: [u8; 32]) -> Result<()> {
+        insert_nullifier(
+            &ctx.accounts.nullifier_shard,
+            &ctx.accounts.payer,
+            &ctx.accounts.system_program,
+            &nullifier_hash,
+            ctx.bumps.nullifier_shard,
+        )?;
+        msg!("Nullifier migrated to shard {}", nullifier_shard(&nullifier_hash));
+        Ok(())
+    }
+
+    /// Upgrade a pool created before the root history and pool registry to
+    /// the current `Pool` layout, and create its deposit queue and window
+    /// (authority only). The pool must be registered with its denomination
+    /// and depth first. Its tree keeps hashing as `TreeVersion::Legacy`, so
+    /// existing notes stay withdrawable; the next rollover starts a
+    /// domain-separated tree. Old pools never counted withdrawals, so they
+    /// are inferred from the vault: notes its balance no longer covers. Old
+    /// vaults were not pre-funded, so the authority tops up their rent.
+    pub fn migrate_pool(ctx: Context<MigratePool>, pool_id: u8) -> Result<()> {
+        check_authority(
+            ctx.accounts.config.authority,
+            ctx.accounts.authority_signers.as_deref(),
+            &ctx.accounts.authority,
+            ctx.remaining_accounts,
+        )?;
+        let entry = *ctx.accounts.registry.find(pool_id).ok_or(ClawLinkError::InvalidPool)?;
+        let info = ctx.accounts.pool.to_account_info();
+        {
+            let data = info.try_borrow_data()?;
+            require!(
+                data.len() == LEGACY_POOL_SPACE && data.starts_with(Pool::DISCRIMINATOR),
+                ClawLinkError::NotLegacyAccount
+            );
+        }
+
+        // 1. Grow the account, topping up its rent
+        let space = 8 + Pool::INIT_SPACE;
+        let shortfall = Rent::get()?.minimum_balance(space).saturating_sub(info.lamports());
+        if shortfall > 0 {
+            system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    system_program::Transfer {
+                        from: ctx.accounts.authority.to_account_info(),
+                        to: info.clone(),
+                    },
+                ),
+                shortfall,
+            )?;
+        }
+        info.resize(space)?;
+
+        // 2. The old fields are a prefix of the new layout, and the zeroed
+        //    rest reads as a SHA256, `Legacy`, fixed-denomination SOL pool
+        let mut pool = Pool::try_deserialize(&mut &info.try_borrow_data()?[..])?;
+        require!(
+            pool.pool_id == pool_id
+                && entry.mode == PoolMode::Fixed
+                && entry.mint.is_none()
+                && entry.denomination == pool.denomination
+                && entry.tree_depth as usize == MERKLE_TREE_DEPTH,
+            ClawLinkError::InvalidPool
+        );
+        pool.tree_depth = entry.tree_depth;
+        pool.store_leaf_accounts = true;
+        pool.root_history[0] = pool.current_root;
+        pool.root_timestamps[0] = Clock::get()?.unix_timestamp;
+        pool.root_leaf_counts[0] = pool.next_index;
+        pool.reset_checkpoints();
+
+        // 3. Rebuild the counters from the tree and the vault, then top up
+        //    the vault's rent on top of the notes it still covers
+        let vault = &ctx.accounts.vault;
+        let outstanding = (vault.lamports() / pool.denomination).min(pool.next_index as u64);
+        pool.total_deposits = pool.next_index as u64;
+        pool.total_withdrawals = pool.total_deposits - outstanding;
+        pool.withdrawal_count = pool.total_withdrawals as u32;
+        pool.try_serialize(&mut &mut info.try_borrow_mut_data()?[..])?;
+        fund_vault(
+            vault,
+            &ctx.accounts.authority,
+            &ctx.accounts.system_program,
+            outstanding * pool.denomination + Rent::get()?.minimum_balance(0),
+        )?;
+
+        let queue = &mut ctx.accounts.deposit_queue;
+        queue.pool_id = pool_id;
+        queue.bump = ctx.bumps.deposit_queue;
+
+        let window = &mut ctx.accounts.deposit_window;
+        window.pool_id = pool_id;
+        window.bump = ctx.bumps.deposit_window;
+
+        msg!(
+            "Pool {} migrated: {} leaves, {} notes outstanding",
+            pool_id, pool.next_index, outstanding
+        );
+        Ok(())
+    }
+
+    /// Close a pool PDA and reclaim rent, including the vault's and, for an
+    /// SPL-token pool, the token vault's (authority only).
+    /// Used when resetting protocol state for redeployment. Refuses while any
+    /// note is still outstanding or the vault holds more than rent, so no
+    /// deposit can be stranded.
+    /// Closing also removes the pool from the registry. Its commitment
+    /// markers stay behind; see `claim_commitment_marker`.
+    pub fn close_pool(ctx: Context<ClosePool>, _pool_id: u8) -> Result<()> {
+        check_authority(
+            ctx.accounts.config.authority,
+            ctx.accounts.authority_signers.as_deref(),
+            &ctx.accounts.authority,
+            ctx.remaining_accounts,
+        )?;
+        let pool = &ctx.accounts.pool;
+        // Shielded notes can have zero amount, so only the vault balance counts
+        require!(
+            pool.mode == PoolMode::Shielded
+                || pool.withdrawal_count as u64 >= pool.total_leaves(),
+            ClawLinkError::PoolNotDrained
+        );
+        require!(
+            ctx.accounts.deposit_queue.pending.is_empty(),
+            ClawLinkError::PoolNotDrained
+        );
+        require!(
+            ctx.accounts.vault.lamports() <= Rent::get()?.minimum_balance(0),
+            ClawLinkError::PoolNotDrained
+        );
+        if pool.mint.is_some() {
+            let token_vault = ctx
+                .accounts
+                .token_vault
+                .as_ref()
+                .ok_or(ClawLinkError::MissingTokenAccounts)?;
+            let token_program = ctx
+                .accounts
+                .token_program
+                .as_ref()
+                .ok_or(ClawLinkError::MissingTokenAccounts)?;
+            require!(token_vault.amount == 0, ClawLinkError::PoolNotDrained);
+
+            let pool_id_bytes = pool.pool_id.to_le_bytes();
+            let vault_bump_bytes = [pool.vault_bump];
+            let signer_seeds: &[&[u8]] = &[b"vault", &pool_id_bytes, &vault_bump_bytes];
+            token::close_account(CpiContext::new_with_signer(
+                token_program.to_account_info(),
+                CloseAccount {
+                    account: token_vault.to_account_info(),
+                    destination: ctx.accounts.authority.to_account_info(),
+                    authority: ctx.accounts.vault.to_account_info(),
+                },
+                &[signer_seeds],
+            ))?;
+        }
+        let vault_rent = ctx.accounts.vault.lamports();
+        if vault_rent > 0 {
+            transfer_from_vault(
+                pool,
+                &ctx.accounts.vault,
+                ctx.accounts.authority.to_account_info(),
+                &ctx.accounts.system_program,
+                vault_rent,
+            )?;
+        }
+        let pool_id = pool.pool_id;
+        ctx.accounts.registry.pools.retain(|p| p.pool_id != pool_id);
+
+        msg!("Pool PDA closed by authority {}", ctx.accounts.authority.key());
+        Ok(())
+    }
+}
+
+// ─── Account Contexts ───────────────────────────────────────────────
+
+#[derive(Accounts)]
+pub struct Initialize<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + ProtocolConfig::INIT_SPACE,
+        seeds = [b"config"],
+        bump
+    )]
+    pub config: Account<'info, ProtocolConfig>,
+
+    pub clink_mint: Account<'info, Mint>,
+
+    #[account(
+        init,
+        payer = authority,
+        token::mint = clink_mint,
+        token::authority = config,
+        seeds = [b"treasury"],
+        bump
+    )]
+    pub treasury: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeRegistry<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + PoolRegistry::INIT_SPACE,
+        seeds = [b"registry"],
+        bump
+    )]
+    pub registry: Box<Account<'info, PoolRegistry>>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProtocolConfig>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// The signer set, when the authority is one; other signing members go in
+    /// the remaining accounts
+    pub authority_signers: Option<Box<Account<'info, AuthoritySigners>>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterPool<'info> {
+    #[account(
+        mut,
+        seeds = [b"registry"],
+        bump = registry.bump,
+    )]
+    pub registry: Box<Account<'info, PoolRegistry>>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProtocolConfig>,
+
+    pub authority: Signer<'info>,
+
+    /// The signer set, when the authority is one; other signing members go in
+    /// the remaining accounts
+    pub authority_signers: Option<Box<Account<'info, AuthoritySigners>>>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u8)]
+pub struct InitializePool<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + Pool::INIT_SPACE,
+        seeds = [b"pool", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        seeds = [b"registry"],
+        bump = registry.bump,
+    )]
+    pub registry: Box<Account<'info, PoolRegistry>>,
+
+    /// CHECK: PDA SOL vault, validated by seeds
+    #[account(
+        mut,
+        seeds = [b"vault", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub vault: SystemAccount<'info>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + DepositQueue::INIT_SPACE,
+        seeds = [b"deposit_queue", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub deposit_queue: Box<Account<'info, DepositQueue>>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + DepositWindow::INIT_SPACE,
+        seeds = [b"deposit_window", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub deposit_window: Box<Account<'info, DepositWindow>>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProtocolConfig>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// The signer set, when the authority is one; other signing members go in
+    /// the remaining accounts
+    pub authority_signers: Option<Box<Account<'info, AuthoritySigners>>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u8)]
+pub struct InitializeTokenVault<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool", pool_id.to_le_bytes().as_ref()],
+        bump = pool.bump,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    /// Pool's SOL vault PDA, the token vault's authority
+    #[account(
+        seeds = [b"vault", pool_id.to_le_bytes().as_ref()],
+        bump = pool.vault_bump,
+    )]
+    pub vault: SystemAccount<'info>,
+
+    pub mint: Account<'info, Mint>,
+
+    #[account(
+        init,
+        payer = authority,
+        token::mint = mint,
+        token::authority = vault,
+        seeds = [b"token_vault", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub token_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProtocolConfig>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// The signer set, when the authority is one; other signing members go in
+    /// the remaining accounts
+    pub authority_signers: Option<Box<Account<'info, AuthoritySigners>>>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(commitment: [u8; 32], pool_id: u8, leaf_index: u32)]
+pub struct DepositCtx<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = clink_mint @ ClawLinkError::InvalidMint,
+    )]
+    pub config: Account<'info, ProtocolConfig>,
+
+    /// CLINK mint — mutable so fees can be burned
+    #[account(mut)]
+    pub clink_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"pool", pool_id.to_le_bytes().as_ref()],
+        bump = pool.bump,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        seeds = [b"registry"],
+        bump = registry.bump,
+    )]
+    pub registry: Box<Account<'info, PoolRegistry>>,
+
+    /// Queued deposits also count against the tree's capacity
+    #[account(
+        seeds = [b"deposit_queue", pool_id.to_le_bytes().as_ref()],
+        bump = deposit_queue.bump,
+    )]
+    pub deposit_queue: Box<Account<'info, DepositQueue>>,
+
+    #[account(
+        mut,
+        seeds = [b"deposit_window", pool_id.to_le_bytes().as_ref()],
+        bump = deposit_window.bump,
+    )]
+    pub deposit_window: Box<Account<'info, DepositWindow>>,
+
+    /// CHECK: PDA SOL vault, validated by seeds
+    #[account(
+        mut,
+        seeds = [b"vault", pool_id.to_le_bytes().as_ref()],
+        bump = pool.vault_bump,
+    )]
+    pub vault: SystemAccount<'info>,
+
+    /// Pool's token vault (SPL-token pools only)
+    #[account(
+        mut,
+        seeds = [b"token_vault", pool_id.to_le_bytes().as_ref()],
+        bump = pool.token_vault_bump,
+    )]
+    pub token_vault: Option<Box<Account<'info, TokenAccount>>>,
+
+    /// Depositor's account for the pool's token (SPL-token pools only)
+    #[account(
+        mut,
+        constraint = depositor_token.owner == depositor.key() @ ClawLinkError::InvalidOwner,
+    )]
+    pub depositor_token: Option<Box<Account<'info, TokenAccount>>>,
+
+    /// Commitment leaf stored on-chain for Merkle proof generation.
+    /// Omitted for events-only pools.
+    #[account(
+        init,
+        payer = depositor,
+        space = 8 + CommitmentLeaf::INIT_SPACE,
+        seeds = [
+            b"leaf",
+            pool_id.to_le_bytes().as_ref(),
+            pool.tree_index.to_le_bytes().as_ref(),
+            leaf_index.to_le_bytes().as_ref(),
+        ],
+        bump
+    )]
+    pub commitment_leaf: Option<Account<'info, CommitmentLeaf>>,
+
+    /// CHECK: Empty marker PDA for this commitment, claimed in the handler;
+    /// already program-owned means a duplicate deposit
+    #[account(
+        mut,
+        seeds = [b"commitment", commitment.as_ref()],
+        bump
+    )]
+    pub commitment_marker: UncheckedAccount<'info>,
+
+    /// Depositor's CLINK token account (fee source)
+    #[account(
+        mut,
+        constraint = depositor_clink.mint == config.clink_mint @ ClawLinkError::InvalidMint,
+        constraint = depositor_clink.owner == depositor.key() @ ClawLinkError::InvalidOwner,
+    )]
+    pub depositor_clink: Account<'info, TokenAccount>,
+
+    /// Treasury token account (fee destination)
+    #[account(
+        mut,
+        seeds = [b"treasury"],
+        bump = config.treasury_bump,
+    )]
+    pub treasury: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(commitment: [u8; 32], pool_id: u8)]
+pub struct QueueDepositCtx<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = clink_mint @ ClawLinkError::InvalidMint,
+    )]
+    pub config: Account<'info, ProtocolConfig>,
+
+    /// CLINK mint — mutable so fees can be burned
+    #[account(mut)]
+    pub clink_mint: Account<'info, Mint>,
+
+    #[account(
+        seeds = [b"pool", pool_id.to_le_bytes().as_ref()],
+        bump = pool.bump,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        seeds = [b"registry"],
+        bump = registry.bump,
+    )]
+    pub registry: Box<Account<'info, PoolRegistry>>,
+
+    #[account(
+        mut,
+        seeds = [b"deposit_queue", pool_id.to_le_bytes().as_ref()],
+        bump = deposit_queue.bump,
+    )]
+    pub deposit_queue: Box<Account<'info, DepositQueue>>,
+
+    #[account(
+        mut,
+        seeds = [b"deposit_window", pool_id.to_le_bytes().as_ref()],
+        bump = deposit_window.bump,
+    )]
+    pub deposit_window: Box<Account<'info, DepositWindow>>,
+
+    /// CHECK: PDA SOL vault, validated by seeds
+    #[account(
+        mut,
+        seeds = [b"vault", pool_id.to_le_bytes().as_ref()],
+        bump = pool.vault_bump,
+    )]
+    pub vault: SystemAccount<'info>,
+
+    /// Pool's token vault (SPL-token pools only)
+    #[account(
+        mut,
+        seeds = [b"token_vault", pool_id.to_le_bytes().as_ref()],
+        bump = pool.token_vault_bump,
+    )]
+    pub token_vault: Option<Box<Account<'info, TokenAccount>>>,
+
+    /// Depositor's account for the pool's token (SPL-token pools only)
+    #[account(
+        mut,
+        constraint = depositor_token.owner == depositor.key() @ ClawLinkError::InvalidOwner,
+    )]
+    pub depositor_token: Option<Box<Account<'info, TokenAccount>>>,
+
+    /// CHECK: Empty marker PDA for this commitment, claimed in the handler;
+    /// already program-owned means a duplicate deposit
+    #[account(
+        mut,
+        seeds = [b"commitment", commitment.as_ref()],
+        bump
+    )]
+    pub commitment_marker: UncheckedAccount<'info>,
+
+    /// Depositor's CLINK token account (fee source)
+    #[account(
+        mut,
+        constraint = depositor_clink.mint == config.clink_mint @ ClawLinkError::InvalidMint,
+        constraint = depositor_clink.owner == depositor.key() @ ClawLinkError::InvalidOwner,
+    )]
+    pub depositor_clink: Account<'info, TokenAccount>,
+
+    /// Treasury token account (fee destination)
+    #[account(
+        mut,
+        seeds = [b"treasury"],
+        bump = config.treasury_bump,
+    )]
+    pub treasury: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(pool_id: u8)]
+pub struct FlushDeposits<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool", pool_id.to_le_bytes().as_ref()],
+        bump = pool.bump,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        mut,
+        seeds = [b"deposit_queue", pool_id.to_le_bytes().as_ref()],
+        bump = deposit_queue.bump,
+    )]
+    pub deposit_queue: Box<Account<'info, DepositQueue>>,
+
+    /// Pays the rent of the flushed deposits' leaf accounts, if any
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(binding: [u8; 32])]
+pub struct CommitWithdrawal<'info> {
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + WithdrawalCommit::INIT_SPACE,
+        seeds = [b"withdraw_commit", binding.as_ref()],
+        bump
+    )]
+    pub withdrawal_commit: Account<'info, WithdrawalCommit>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(
+    secret: [u8; 32],
+    nullifier_preimage: [u8; 32],
+    nullifier_hash: [u8; 32],
+    tree_index: u32,
+)]
+pub struct WithdrawCtx<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProtocolConfig>,
+
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.to_le_bytes().as_ref()],
+        bump = pool.bump,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        seeds = [b"registry"],
+        bump = registry.bump,
+    )]
+    pub registry: Box<Account<'info, PoolRegistry>>,
+
+    /// Archived tree the note belongs to, when it isn't the pool's live tree
+    #[account(
+        seeds = [b"tree", pool.pool_id.to_le_bytes().as_ref(), tree_index.to_le_bytes().as_ref()],
+        bump = tree_archive.bump,
+    )]
+    pub tree_archive: Option<Box<Account<'info, TreeArchive>>>,
+
+    /// CHECK: PDA SOL vault, validated by seeds
+    #[account(
+        mut,
+        seeds = [b"vault", pool.pool_id.to_le_bytes().as_ref()],
+        bump = pool.vault_bump,
+    )]
+    pub vault: SystemAccount<'info>,
+
+    /// CHECK: Shard holding `nullifier_hash`, created and grown in the
+    /// handler; already holding it means a double-spend
+    #[account(
+        mut,
+        seeds = [b"nullifier_shard", &[nullifier_hash[NULLIFIER_SHARD_BYTE]]],
+        bump
+    )]
+    pub nullifier_shard: UncheckedAccount<'info>,
+
+    /// CHECK: Pre-shard nullifier PDA; must not exist (see `migrate_nullifier`)
+    #[account(
+        seeds = [b"nullifier", nullifier_hash.as_ref()],
+        bump
+    )]
+    pub legacy_nullifier: UncheckedAccount<'info>,
+
+    /// Earlier commit binding this note to `recipient`; closed on success
+    #[account(
+        mut,
+        seeds = [b"withdraw_commit", withdrawal_commit.binding.as_ref()],
+        bump = withdrawal_commit.bump,
+        close = payer,
+    )]
+    pub withdrawal_commit: Account<'info, WithdrawalCommit>,
+
+    /// CHECK: Any account can receive SOL — bound by the withdrawal commit
+    #[account(mut)]
+    pub recipient: UncheckedAccount<'info>,
+
+    /// CHECK: Receives the fee — bound by the withdrawal commit
+    #[account(mut)]
+    pub relayer: UncheckedAccount<'info>,
+
+    /// Pays for the nullifier account; usually the relayer
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    /// Pool's token vault (SPL-token pools only)
+    #[account(
+        mut,
+        seeds = [b"token_vault", pool.pool_id.to_le_bytes().as_ref()],
+        bump = pool.token_vault_bump,
+    )]
+    pub token_vault: Option<Box<Account<'info, TokenAccount>>>,
+
+    /// Recipient's account for the pool's token (SPL-token pools only)
+    #[account(mut)]
+    pub recipient_token: Option<Box<Account<'info, TokenAccount>>>,
+
+    /// Relayer's account for the pool's token (SPL-token pools only)
+    #[account(mut)]
+    pub relayer_token: Option<Box<Account<'info, TokenAccount>>>,
+
+    pub token_program: Option<Program<'info, Token>>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(
+    proof_a: [u8; 64],
+    proof_b: [u8; 128],
+    proof_c: [u8; 64],
+    tree_index: u32,
+    root: [u8; 32],
+    nullifier_hash: [u8; 32],
+)]
+pub struct WithdrawZkCtx<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProtocolConfig>,
+
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.to_le_bytes().as_ref()],
+        bump = pool.bump,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        seeds = [b"registry"],
+        bump = registry.bump,
+    )]
+    pub registry: Box<Account<'info, PoolRegistry>>,
+
+    /// Archived tree the note belongs to, when it isn't the pool's live tree
+    #[account(
+        seeds = [b"tree", pool.pool_id.to_le_bytes().as_ref(), tree_index.to_le_bytes().as_ref()],
+        bump = tree_archive.bump,
+    )]
+    pub tree_archive: Option<Box<Account<'info, TreeArchive>>>,
+
+    /// CHECK: PDA SOL vault, validated by seeds
+    #[account(
+        mut,
+        seeds = [b"vault", pool.pool_id.to_le_bytes().as_ref()],
+        bump = pool.vault_bump,
+    )]
+    pub vault: SystemAccount<'info>,
+
+    #[account(
+        seeds = [b"verifying_key", ZkCircuit::Withdraw.seed()],
+        bump = verifying_key.bump,
+    )]
+    pub verifying_key: Box<Account<'info, VerifyingKey>>,
+
+    /// CHECK: Shard holding `nullifier_hash`, grown in the handler. Shared
+    /// with `withdraw`, so a note can't be spent on both paths.
+    #[account(
+        mut,
+        seeds = [b"nullifier_shard", &[nullifier_hash[NULLIFIER_SHARD_BYTE]]],
+        bump
+    )]
+    pub nullifier_shard: UncheckedAccount<'info>,
+
+    /// CHECK: Pre-shard nullifier PDA; must not exist (see `migrate_nullifier`)
+    #[account(
+        seeds = [b"nullifier", nullifier_hash.as_ref()],
+        bump
+    )]
+    pub legacy_nullifier: UncheckedAccount<'info>,
+
+    /// CHECK: Any account can receive SOL — bound by the proof's public inputs
+    #[account(mut)]
+    pub recipient: UncheckedAccount<'info>,
+
+    /// CHECK: Receives the fee — bound by the proof's public inputs
+    #[account(mut)]
+    pub relayer: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    /// Pool's token vault (SPL-token pools only)
+    #[account(
+        mut,
+        seeds = [b"token_vault", pool.pool_id.to_le_bytes().as_ref()],
+        bump = pool.token_vault_bump,
+    )]
+    pub token_vault: Option<Box<Account<'info, TokenAccount>>>,
+
+    /// Recipient's account for the pool's token (SPL-token pools only)
+    #[account(mut)]
+    pub recipient_token: Option<Box<Account<'info, TokenAccount>>>,
+
+    /// Relayer's account for the pool's token (SPL-token pools only)
+    #[account(mut)]
+    pub relayer_token: Option<Box<Account<'info, TokenAccount>>>,
+
+    pub token_program: Option<Program<'info, Token>>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(
+    proof_a: [u8; 64],
+    proof_b: [u8; 128],
+    proof_c: [u8; 64],
+    tree_index: u32,
+    root: [u8; 32],
+    nullifier_hash: [u8; 32],
+    new_commitment: [u8; 32],
+)]
+pub struct TransferNoteCtx<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProtocolConfig>,
+
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.to_le_bytes().as_ref()],
+        bump = pool.bump,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        seeds = [b"registry"],
+        bump = registry.bump,
+    )]
+    pub registry: Box<Account<'info, PoolRegistry>>,
+
+    /// Archived tree the spent note belongs to, when it isn't the pool's live tree
+    #[account(
+        seeds = [b"tree", pool.pool_id.to_le_bytes().as_ref(), tree_index.to_le_bytes().as_ref()],
+        bump = tree_archive.bump,
+    )]
+    pub tree_archive: Option<Box<Account<'info, TreeArchive>>>,
+
+    /// Queued deposits also count against the tree's capacity
+    #[account(
+        seeds = [b"deposit_queue", pool.pool_id.to_le_bytes().as_ref()],
+        bump = deposit_queue.bump,
+    )]
+    pub deposit_queue: Box<Account<'info, DepositQueue>>,
+
+    #[account(
+        seeds = [b"verifying_key", ZkCircuit::Transfer.seed()],
+        bump = verifying_key.bump,
+    )]
+    pub verifying_key: Box<Account<'info, VerifyingKey>>,
+
+    /// CHECK: Shard holding `nullifier_hash`, grown in the handler. Shared
+    /// with the withdrawals.
+    #[account(
+        mut,
+        seeds = [b"nullifier_shard", &[nullifier_hash[NULLIFIER_SHARD_BYTE]]],
+        bump
+    )]
+    pub nullifier_shard: UncheckedAccount<'info>,
+
+    /// CHECK: Pre-shard nullifier PDA; must not exist (see `migrate_nullifier`)
+    #[account(
+        seeds = [b"nullifier", nullifier_hash.as_ref()],
+        bump
+    )]
+    pub legacy_nullifier: UncheckedAccount<'info>,
+
+    /// CHECK: Empty marker PDA for the new commitment, claimed in the handler;
+    /// already program-owned means a duplicate commitment
+    #[account(
+        mut,
+        seeds = [b"commitment", new_commitment.as_ref()],
+        bump
+    )]
+    pub commitment_marker: UncheckedAccount<'info>,
+
+    /// Leaf account for the new commitment. Omitted for events-only pools.
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + CommitmentLeaf::INIT_SPACE,
+        seeds = [
+            b"leaf",
+            pool.pool_id.to_le_bytes().as_ref(),
+            pool.tree_index.to_le_bytes().as_ref(),
+            pool.next_index.to_le_bytes().as_ref(),
+        ],
+        bump
+    )]
+    pub commitment_leaf: Option<Box<Account<'info, CommitmentLeaf>>>,
+
+    /// Pays for the new accounts; usually the sender or a relayer
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(tree_index: u32, proof: TransactProof)]
+pub struct TransactCtx<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProtocolConfig>,
+
+    #[account(
+        mut,
+        seeds = [b"pool", pool.pool_id.to_le_bytes().as_ref()],
+        bump = pool.bump,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        seeds = [b"registry"],
+        bump = registry.bump,
+    )]
+    pub registry: Box<Account<'info, PoolRegistry>>,
+
+    /// Archived tree the inputs belong to, when it isn't the pool's live tree
+    #[account(
+        seeds = [b"tree", pool.pool_id.to_le_bytes().as_ref(), tree_index.to_le_bytes().as_ref()],
+        bump = tree_archive.bump,
+    )]
+    pub tree_archive: Option<Box<Account<'info, TreeArchive>>>,
+
+    /// CHECK: PDA SOL vault, validated by seeds
+    #[account(
+        mut,
+        seeds = [b"vault", pool.pool_id.to_le_bytes().as_ref()],
+        bump = pool.vault_bump,
+    )]
+    pub vault: SystemAccount<'info>,
+
+    /// Deposit limits, counted only when `ext_amount` is positive
+    #[account(
+        mut,
+        seeds = [b"deposit_window", pool.pool_id.to_le_bytes().as_ref()],
+        bump = deposit_window.bump,
+    )]
+    pub deposit_window: Box<Account<'info, DepositWindow>>,
+
+    #[account(
+        seeds = [b"verifying_key", ZkCircuit::Transact.seed()],
+        bump = verifying_key.bump,
+    )]
+    pub verifying_key: Box<Account<'info, VerifyingKey>>,
+
+    /// CHECK: Shard holding the first input's nullifier, grown in the
+    /// handler. Shared with the fixed-denomination withdrawals.
+    #[account(
+        mut,
+        seeds = [b"nullifier_shard", &[proof.input_nullifiers[0][NULLIFIER_SHARD_BYTE]]],
+        bump
+    )]
+    pub nullifier_shard_0: UncheckedAccount<'info>,
+
+    /// CHECK: Pre-shard nullifier PDA; must not exist (see `migrate_nullifier`)
+    #[account(
+        seeds = [b"nullifier", &proof.input_nullifiers[0]],
+        bump
+    )]
+    pub legacy_nullifier_0: UncheckedAccount<'info>,
+
+    /// CHECK: Shard holding the second input's nullifier; the same account
+    /// as `nullifier_shard_0` when both nullifiers share a shard
+    #[account(
+        mut,
+        seeds = [b"nullifier_shard", &[proof.input_nullifiers[1][NULLIFIER_SHARD_BYTE]]],
+        bump
+    )]
+    pub nullifier_shard_1: UncheckedAccount<'info>,
+
+    /// CHECK: Pre-shard nullifier PDA of the second input; must not exist
+    #[account(
+        seeds = [b"nullifier", &proof.input_nullifiers[1]],
+        bump
+    )]
+    pub legacy_nullifier_1: UncheckedAccount<'info>,
+
+    /// CHECK: Receives withdrawn SOL — bound by the proof's ext_data_hash
+    #[account(mut)]
+    pub recipient: UncheckedAccount<'info>,
+
+    /// CHECK: Receives the fee — bound by the proof's ext_data_hash
+    #[account(mut)]
+    pub relayer: UncheckedAccount<'info>,
+
+    /// CHECK: Empty marker PDA for the first output commitment, claimed in
+    /// the handler; already program-owned means a duplicate commitment
+    #[account(
+        mut,
+        seeds = [b"commitment", &proof.output_commitments[0]],
+        bump
+    )]
+    pub commitment_marker_0: UncheckedAccount<'info>,
 
-        // Verify Merkle proof
-        require!(proof.len() == MERKLE_TREE_DEPTH, ClawLinkError::InvalidProof);
-        let mut current_hash = commitment;
-        let mut index = leaf_index;
-        for i in 0..MERKLE_TREE_DEPTH {
-            if index % 2 == 0 {
-                current_hash = hash_pair(&current_hash, &proof[i]);
-            } else {
-                current_hash = hash_pair(&proof[i], &current_hash);
-            }
-            index /= 2;
-        }
-        require!(current_hash == pool.current_root, ClawLinkError::InvalidProof);
+    /// CHECK: Empty marker PDA for the second output commitment
+    #[account(
+        mut,
+        seeds = [b"commitment", &proof.output_commitments[1]],
+        bump
+    )]
+    pub commitment_marker_1: UncheckedAccount<'info>,
 
-        // 3. Record nullifier (account init prevents double-spend)
-        let nullifier_account = &mut ctx.accounts.nullifier_account;
-        nullifier_account.nullifier = nullifier_hash;
-        nullifier_account.pool_id = pool.pool_id;
-        nullifier_account.bump = ctx.bumps.nullifier_account;
+    /// Pays for the nullifier accounts and commitment markers, and funds deposits
+    #[account(mut)]
+    pub payer: Signer<'info>,
 
-        // 4. Transfer SOL from vault to recipient via CPI with PDA signing
-        let denomination = pool.denomination;
-        let vault_lamports = ctx.accounts.vault.to_account_info().lamports();
-        require!(vault_lamports >= denomination, ClawLinkError::InsufficientVaultBalance);
+    /// CLINK mint — mutable so fees can be burned (deposits only)
+    #[account(
+        mut,
+        address = config.clink_mint @ ClawLinkError::InvalidMint,
+    )]
+    pub clink_mint: Option<Box<Account<'info, Mint>>>,
 
-        let pool_id_bytes = pool.pool_id.to_le_bytes();
-        let vault_bump_bytes = [pool.vault_bump];
-        let signer_seeds: &[&[u8]] = &[b"vault", &pool_id_bytes, &vault_bump_bytes];
-        system_program::transfer(
-            CpiContext::new_with_signer(
-                ctx.accounts.system_program.to_account_info(),
-                system_program::Transfer {
-                    from: ctx.accounts.vault.to_account_info(),
-                    to: ctx.accounts.recipient.to_account_info(),
-                },
-                &[signer_seeds],
-            ),
-            denomination,
-        )?;
+    /// Payer's CLINK token account (deposits only)
+    #[account(
+        mut,
+        constraint = payer_clink.mint == config.clink_mint @ ClawLinkError::InvalidMint,
+        constraint = payer_clink.owner == payer.key() @ ClawLinkError::InvalidOwner,
+    )]
+    pub payer_clink: Option<Box<Account<'info, TokenAccount>>>,
 
-        msg!(
-            "Withdrawn {} lamports from pool {} to {}",
-            denomination,
-            pool.pool_id,
-            ctx.accounts.recipient.key()
-        );
+    /// Treasury token account (deposits only)
+    #[account(
+        mut,
+        seeds = [b"treasury"],
+        bump = config.treasury_bump,
+    )]
+    pub treasury: Option<Box<Account<'info, TokenAccount>>>,
 
-        Ok(())
-    }
+    pub system_program: Program<'info, System>,
+    pub token_program: Option<Program<'info, Token>>,
+}
 
-    /// Update the CLINK fee amount (authority only).
-    pub fn update_fee(ctx: Context<UpdateConfig>, new_fee: u64) -> Result<()> {
-        let config = &mut ctx.accounts.config;
-        config.fee_amount = new_fee;
-        msg!("Fee updated to {} CLINK", new_fee);
-        Ok(())
-    }
+#[derive(Accounts)]
+#[instruction(circuit: ZkCircuit)]
+pub struct InitializeVerifyingKey<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + VerifyingKey::INIT_SPACE,
+        seeds = [b"verifying_key", circuit.seed()],
+        bump
+    )]
+    pub verifying_key: Box<Account<'info, VerifyingKey>>,
 
-    /// Close the config PDA and reclaim rent (authority only).
-    /// Used when resetting protocol state for redeployment.
-    pub fn close_config(ctx: Context<CloseConfig>) -> Result<()> {
-        msg!("Config PDA closed by authority {}", ctx.accounts.authority.key());
-        Ok(())
-    }
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProtocolConfig>,
 
-    /// Close a pool PDA and reclaim rent (authority only).
-    /// Used when resetting protocol state for redeployment.
-    pub fn close_pool(ctx: Context<ClosePool>, _pool_id: u8) -> Result<()> {
-        msg!("Pool PDA closed by authority {}", ctx.accounts.authority.key());
-        Ok(())
-    }
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// The signer set, when the authority is one; other signing members go in
+    /// the remaining accounts
+    pub authority_signers: Option<Box<Account<'info, AuthoritySigners>>>,
+
+    pub system_program: Program<'info, System>,
 }
 
-// ─── Account Contexts ───────────────────────────────────────────────
+#[derive(Accounts)]
+#[instruction(circuit: ZkCircuit)]
+pub struct UpdateVerifyingKey<'info> {
+    #[account(
+        mut,
+        seeds = [b"verifying_key", circuit.seed()],
+        bump = verifying_key.bump,
+    )]
+    pub verifying_key: Box<Account<'info, VerifyingKey>>,
 
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProtocolConfig>,
+
+    pub authority: Signer<'info>,
+
+    /// The signer set, when the authority is one; other signing members go in
+    /// the remaining accounts
+    pub authority_signers: Option<Box<Account<'info, AuthoritySigners>>>,
+}
+
+#[event_cpi]
 #[derive(Accounts)]
-pub struct Initialize<'info> {
+pub struct AuditPool<'info> {
     #[account(
-        init,
-        payer = authority,
-        space = 8 + ProtocolConfig::INIT_SPACE,
+        seeds = [b"pool", pool.pool_id.to_le_bytes().as_ref()],
+        bump = pool.bump,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        seeds = [b"deposit_queue", pool.pool_id.to_le_bytes().as_ref()],
+        bump = deposit_queue.bump,
+    )]
+    pub deposit_queue: Box<Account<'info, DepositQueue>>,
+
+    #[account(
+        seeds = [b"vault", pool.pool_id.to_le_bytes().as_ref()],
+        bump = pool.vault_bump,
+    )]
+    pub vault: SystemAccount<'info>,
+
+    /// Pool's token vault (SPL-token pools only)
+    #[account(
+        seeds = [b"token_vault", pool.pool_id.to_le_bytes().as_ref()],
+        bump = pool.token_vault_bump,
+    )]
+    pub token_vault: Option<Box<Account<'info, TokenAccount>>>,
+}
+
+#[derive(Accounts)]
+pub struct PoolView<'info> {
+    #[account(
+        seeds = [b"pool", pool.pool_id.to_le_bytes().as_ref()],
+        bump = pool.bump,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+    #[account(
+        mut,
         seeds = [b"config"],
-        bump
+        bump = config.bump,
     )]
     pub config: Account<'info, ProtocolConfig>,
 
-    pub clink_mint: Account<'info, Mint>,
+    pub authority: Signer<'info>,
+
+    /// The signer set, when the authority is one; other signing members go in
+    /// the remaining accounts
+    pub authority_signers: Option<Box<Account<'info, AuthoritySigners>>>,
+}
 
+#[derive(Accounts)]
+pub struct AcceptAuthority<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProtocolConfig>,
+
+    /// The proposed authority, or a member of the proposed signer set
+    pub authority: Signer<'info>,
+
+    /// The proposed signer set, when accepting on its behalf; other signing
+    /// members go in the remaining accounts
+    pub authority_signers: Option<Box<Account<'info, AuthoritySigners>>>,
+}
+
+#[derive(Accounts)]
+#[instruction(set_id: u8)]
+pub struct CreateAuthoritySigners<'info> {
     #[account(
         init,
-        payer = authority,
-        token::mint = clink_mint,
-        token::authority = config,
-        seeds = [b"treasury"],
+        payer = payer,
+        space = 8 + AuthoritySigners::INIT_SPACE,
+        seeds = [b"authority_signers", payer.key().as_ref(), set_id.to_le_bytes().as_ref()],
         bump
     )]
-    pub treasury: Account<'info, TokenAccount>,
+    pub authority_signers: Box<Account<'info, AuthoritySigners>>,
 
     #[account(mut)]
-    pub authority: Signer<'info>,
+    pub payer: Signer<'info>,
 
     pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
-    pub rent: Sysvar<'info, Rent>,
 }
 
 #[derive(Accounts)]
-#[instruction(pool_id: u8)]
-pub struct InitializePool<'info> {
+pub struct Pause<'info> {
     #[account(
-        init,
-        payer = authority,
-        space = 8 + Pool::INIT_SPACE,
-        seeds = [b"pool", pool_id.to_le_bytes().as_ref()],
-        bump
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
     )]
-    pub pool: Account<'info, Pool>,
+    pub config: Account<'info, ProtocolConfig>,
+
+    /// Guardian, or the authority (a member of it, for a signer set)
+    pub signer: Signer<'info>,
+
+    /// The signer set, when the authority is one; other signing members go in
+    /// the remaining accounts
+    pub authority_signers: Option<Box<Account<'info, AuthoritySigners>>>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawTreasury<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProtocolConfig>,
 
-    /// CHECK: PDA SOL vault, validated by seeds
     #[account(
         mut,
-        seeds = [b"vault", pool_id.to_le_bytes().as_ref()],
-        bump
+        seeds = [b"treasury"],
+        bump = config.treasury_bump,
     )]
-    pub vault: SystemAccount<'info>,
+    pub treasury: Account<'info, TokenAccount>,
+
+    /// Destination CLINK token account
+    #[account(
+        mut,
+        constraint = destination.mint == config.clink_mint @ ClawLinkError::InvalidMint,
+    )]
+    pub destination: Account<'info, TokenAccount>,
+
+    pub authority: Signer<'info>,
+
+    /// The signer set, when the authority is one; other signing members go in
+    /// the remaining accounts
+    pub authority_signers: Option<Box<Account<'info, AuthoritySigners>>>,
+
+    pub token_program: Program<'info, Token>,
+}
 
+#[derive(Accounts)]
+pub struct CloseConfig<'info> {
     #[account(
+        mut,
         seeds = [b"config"],
         bump = config.bump,
+        close = authority,
     )]
     pub config: Account<'info, ProtocolConfig>,
 
+    /// Treasury token account — must be empty; closed alongside the config
     #[account(
         mut,
-        constraint = authority.key() == config.authority @ ClawLinkError::Unauthorized
+        seeds = [b"treasury"],
+        bump = config.treasury_bump,
     )]
+    pub treasury: Account<'info, TokenAccount>,
+
+    #[account(mut)]
     pub authority: Signer<'info>,
 
-    pub system_program: Program<'info, System>,
+    /// The signer set, when the authority is one; other signing members go in
+    /// the remaining accounts
+    pub authority_signers: Option<Box<Account<'info, AuthoritySigners>>>,
+
+    pub token_program: Program<'info, Token>,
 }
 
 #[derive(Accounts)]
-#[instruction(commitment: [u8; 32], pool_id: u8, leaf_index: u32)]
-pub struct DepositCtx<'info> {
+#[instruction(pool_id: u8)]
+pub struct UpdateDepositLimits<'info> {
+    #[account(
+        mut,
+        seeds = [b"deposit_window", pool_id.to_le_bytes().as_ref()],
+        bump = deposit_window.bump,
+    )]
+    pub deposit_window: Box<Account<'info, DepositWindow>>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProtocolConfig>,
+
+    pub authority: Signer<'info>,
+
+    /// The signer set, when the authority is one; other signing members go in
+    /// the remaining accounts
+    pub authority_signers: Option<Box<Account<'info, AuthoritySigners>>>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u8)]
+pub struct UpdatePool<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool", pool_id.to_le_bytes().as_ref()],
+        bump = pool.bump,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
     #[account(
         seeds = [b"config"],
         bump = config.bump,
     )]
     pub config: Account<'info, ProtocolConfig>,
 
+    pub authority: Signer<'info>,
+
+    /// The signer set, when the authority is one; other signing members go in
+    /// the remaining accounts
+    pub authority_signers: Option<Box<Account<'info, AuthoritySigners>>>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u8)]
+pub struct RolloverTree<'info> {
+    #[account(
+        mut,
+        seeds = [b"pool", pool_id.to_le_bytes().as_ref()],
+        bump = pool.bump,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + TreeArchive::INIT_SPACE,
+        seeds = [b"tree", pool_id.to_le_bytes().as_ref(), pool.tree_index.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub tree_archive: Box<Account<'info, TreeArchive>>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u8, tree_index: u32, leaf_index: u32)]
+pub struct CloseLeaf<'info> {
+    #[account(
+        seeds = [b"pool", pool_id.to_le_bytes().as_ref()],
+        bump = pool.bump,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
     #[account(
         mut,
+        seeds = [
+            b"leaf",
+            pool_id.to_le_bytes().as_ref(),
+            tree_index.to_le_bytes().as_ref(),
+            leaf_index.to_le_bytes().as_ref(),
+        ],
+        bump = commitment_leaf.bump,
+        has_one = payer @ ClawLinkError::InvalidOwner,
+        close = payer,
+    )]
+    pub commitment_leaf: Account<'info, CommitmentLeaf>,
+
+    /// CHECK: rent refund destination, must match `commitment_leaf.payer`
+    #[account(mut)]
+    pub payer: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_id: u8, leaf_index: u32)]
+pub struct CloseLegacyLeaf<'info> {
+    #[account(
         seeds = [b"pool", pool_id.to_le_bytes().as_ref()],
         bump = pool.bump,
     )]
-    pub pool: Account<'info, Pool>,
+    pub pool: Box<Account<'info, Pool>>,
 
-    /// CHECK: PDA SOL vault, validated by seeds
+    /// CHECK: Leaf in its pre-upgrade layout, checked and closed in the handler
     #[account(
         mut,
-        seeds = [b"vault", pool_id.to_le_bytes().as_ref()],
-        bump = pool.vault_bump,
+        seeds = [
+            b"leaf",
+            pool_id.to_le_bytes().as_ref(),
+            leaf_index.to_le_bytes().as_ref(),
+        ],
+        bump,
+        owner = crate::ID,
     )]
-    pub vault: SystemAccount<'info>,
+    pub legacy_leaf: UncheckedAccount<'info>,
 
-    /// Commitment leaf stored on-chain for Merkle proof generation
     #[account(
-        init,
-        payer = depositor,
-        space = 8 + CommitmentLeaf::INIT_SPACE,
-        seeds = [b"leaf", pool_id.to_le_bytes().as_ref(), leaf_index.to_le_bytes().as_ref()],
-        bump
+        seeds = [b"config"],
+        bump = config.bump,
     )]
-    pub commitment_leaf: Account<'info, CommitmentLeaf>,
+    pub config: Account<'info, ProtocolConfig>,
 
-    /// Depositor's CLINK token account (fee source)
+    /// CHECK: destination of the reclaimed rent, must be the protocol authority
+    #[account(mut, address = config.authority @ ClawLinkError::Unauthorized)]
+    pub authority: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(nullifier_hash: [u8; 32])]
+pub struct MigrateNullifier<'info> {
     #[account(
         mut,
-        constraint = depositor_clink.mint == config.clink_mint @ ClawLinkError::InvalidMint,
-        constraint = depositor_clink.owner == depositor.key() @ ClawLinkError::InvalidOwner,
+        seeds = [b"nullifier", nullifier_hash.as_ref()],
+        bump = legacy_nullifier.bump,
+        close = payer,
     )]
-    pub depositor_clink: Account<'info, TokenAccount>,
+    pub legacy_nullifier: Account<'info, NullifierAccount>,
 
-    /// Treasury token account (fee destination)
+    /// CHECK: Shard the nullifier moves into, grown in the handler
     #[account(
         mut,
-        seeds = [b"treasury"],
-        bump = config.treasury_bump,
+        seeds = [b"nullifier_shard", &[nullifier_hash[NULLIFIER_SHARD_BYTE]]],
+        bump
     )]
-    pub treasury: Account<'info, TokenAccount>,
+    pub nullifier_shard: UncheckedAccount<'info>,
 
     #[account(mut)]
-    pub depositor: Signer<'info>,
+    pub payer: Signer<'info>,
 
     pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
 }
 
 #[derive(Accounts)]
-#[instruction(secret: [u8; 32], nullifier_preimage: [u8; 32], nullifier_hash: [u8; 32])]
-pub struct WithdrawCtx<'info> {
+#[instruction(pool_id: u8)]
+pub struct MigratePool<'info> {
+    /// CHECK: Pool in its pre-upgrade layout, checked and grown in the handler
     #[account(
-        seeds = [b"pool", pool.pool_id.to_le_bytes().as_ref()],
-        bump = pool.bump,
+        mut,
+        seeds = [b"pool", pool_id.to_le_bytes().as_ref()],
+        bump,
+        owner = crate::ID,
+    )]
+    pub pool: UncheckedAccount<'info>,
+
+    #[account(
+        seeds = [b"registry"],
+        bump = registry.bump,
     )]
-    pub pool: Account<'info, Pool>,
+    pub registry: Box<Account<'info, PoolRegistry>>,
 
     /// CHECK: PDA SOL vault, validated by seeds
     #[account(
         mut,
-        seeds = [b"vault", pool.pool_id.to_le_bytes().as_ref()],
-        bump = pool.vault_bump,
+        seeds = [b"vault", pool_id.to_le_bytes().as_ref()],
+        bump
     )]
     pub vault: SystemAccount<'info>,
 
-    /// Nullifier account — init ensures no double-spend.
-    /// PDA seeded by the nullifier_hash (computed client-side, verified on-chain).
-    /// If this account already exists, tx fails → prevents double-spend.
     #[account(
         init,
-        payer = payer,
-        space = 8 + NullifierAccount::INIT_SPACE,
-        seeds = [b"nullifier", nullifier_hash.as_ref()],
+        payer = authority,
+        space = 8 + DepositQueue::INIT_SPACE,
+        seeds = [b"deposit_queue", pool_id.to_le_bytes().as_ref()],
         bump
     )]
-    pub nullifier_account: Account<'info, NullifierAccount>,
-
-    /// CHECK: Any account can receive SOL — intentional for privacy
-    #[account(mut)]
-    pub recipient: UncheckedAccount<'info>,
-
-    #[account(mut)]
-    pub payer: Signer<'info>,
+    pub deposit_queue: Box<Account<'info, DepositQueue>>,
 
-    pub system_program: Program<'info, System>,
-}
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + DepositWindow::INIT_SPACE,
+        seeds = [b"deposit_window", pool_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub deposit_window: Box<Account<'info, DepositWindow>>,
 
-#[derive(Accounts)]
-pub struct UpdateConfig<'info> {
     #[account(
-        mut,
         seeds = [b"config"],
         bump = config.bump,
-        constraint = config.authority == authority.key() @ ClawLinkError::Unauthorized,
     )]
     pub config: Account<'info, ProtocolConfig>,
 
+    #[account(mut)]
     pub authority: Signer<'info>,
+
+    /// The signer set, when the authority is one; other signing members go in
+    /// the remaining accounts
+    pub authority_signers: Option<Box<Account<'info, AuthoritySigners>>>,
+
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct CloseConfig<'info> {
+#[instruction(pool_id: u8)]
+pub struct ClosePool<'info> {
     #[account(
         mut,
-        seeds = [b"config"],
-        bump = config.bump,
-        constraint = config.authority == authority.key() @ ClawLinkError::Unauthorized,
+        seeds = [b"pool", pool_id.to_le_bytes().as_ref()],
+        bump = pool.bump,
         close = authority,
     )]
-    pub config: Account<'info, ProtocolConfig>,
+    pub pool: Box<Account<'info, Pool>>,
 
-    /// Treasury token account — must also be closed
     #[account(
         mut,
-        seeds = [b"treasury"],
-        bump = config.treasury_bump,
+        seeds = [b"deposit_queue", pool_id.to_le_bytes().as_ref()],
+        bump = deposit_queue.bump,
+        close = authority,
     )]
-    pub treasury: Account<'info, TokenAccount>,
+    pub deposit_queue: Box<Account<'info, DepositQueue>>,
 
-    #[account(mut)]
-    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"deposit_window", pool_id.to_le_bytes().as_ref()],
+        bump = deposit_window.bump,
+        close = authority,
+    )]
+    pub deposit_window: Box<Account<'info, DepositWindow>>,
 
-    pub token_program: Program<'info, Token>,
-}
+    #[account(
+        mut,
+        seeds = [b"registry"],
+        bump = registry.bump,
+    )]
+    pub registry: Box<Account<'info, PoolRegistry>>,
 
-#[derive(Accounts)]
-#[instruction(pool_id: u8)]
-pub struct ClosePool<'info> {
+    /// CHECK: PDA SOL vault, validated by seeds
     #[account(
         mut,
-        seeds = [b"pool", pool_id.to_le_bytes().as_ref()],
-        bump = pool.bump,
-        close = authority,
+        seeds = [b"vault", pool_id.to_le_bytes().as_ref()],
+        bump = pool.vault_bump,
     )]
-    pub pool: Account<'info, Pool>,
+    pub vault: SystemAccount<'info>,
+
+    /// Pool's token vault (SPL-token pools only), closed to the authority
+    #[account(
+        mut,
+        seeds = [b"token_vault", pool_id.to_le_bytes().as_ref()],
+        bump = pool.token_vault_bump,
+    )]
+    pub token_vault: Option<Box<Account<'info, TokenAccount>>>,
 
     #[account(
         seeds = [b"config"],
         bump = config.bump,
-        constraint = config.authority == authority.key() @ ClawLinkError::Unauthorized,
     )]
     pub config: Account<'info, ProtocolConfig>,
 
     #[account(mut)]
     pub authority: Signer<'info>,
+
+    /// The signer set, when the authority is one; other signing members go in
+    /// the remaining accounts
+    pub authority_signers: Option<Box<Account<'info, AuthoritySigners>>>,
+
+    pub system_program: Program<'info, System>,
+
+    pub token_program: Option<Program<'info, Token>>,
 }
 
 // ─── State Accounts ─────────────────────────────────────────────────
@@ -539,6 +4029,24 @@ pub struct ProtocolConfig {
     pub treasury: Pubkey,         // 32
     pub bump: u8,                 // 1
     pub treasury_bump: u8,        // 1
+    pub max_relayer_fee_bps: u16, // 2
+    pub burn_fees: bool,          // 1
+    pub guardian: Pubkey,         // 32
+    pub paused_deposits: bool,    // 1
+    pub paused_withdrawals: bool, // 1
+    pub pending_authority: Option<Pubkey>, // 1 + 32
+}
+
+/// An M-of-N signer set that can stand in for `ProtocolConfig.authority`:
+/// once the authority is this account's address, authority-only
+/// instructions need `threshold` of `signers` to sign.
+#[account]
+#[derive(InitSpace)]
+pub struct AuthoritySigners {
+    pub threshold: u8, // 1
+    #[max_len(MAX_AUTHORITY_SIGNERS)]
+    pub signers: Vec<Pubkey>, // 4 + 10*32 = 324
+    pub bump: u8, // 1
 }
 
 #[account]
@@ -552,6 +4060,418 @@ pub struct Pool {
     pub current_root: [u8; 32],                    // 32
     #[max_len(20)]
     pub filled_subtrees: Vec<[u8; 32]>,            // 4 + 20*32 = 644
+    pub root_history: [[u8; 32]; ROOT_HISTORY_SIZE], // 30*32 = 960
+    pub current_root_index: u8,                    // 1
+    pub hash_function: HashFunction,               // 1
+    /// Fixed pools: notes spent, one per nullifier, by `withdraw`, `withdraw_zk`
+    /// or `transfer_note`. Every leaf is spent once it reaches `total_leaves`,
+    /// which is what `close_pool` checks.
+    pub withdrawal_count: u32,                     // 4
+    pub sunset: bool,                              // 1
+    pub tree_depth: u8,                            // 1
+    pub mint: Option<Pubkey>,                      // 1 + 32
+    pub token_vault_bump: u8,                      // 1
+    pub store_leaf_accounts: bool,                 // 1
+    /// Merkle hashing scheme; `Legacy` only for trees of pools upgraded by `migrate_pool`
+    pub tree_version: TreeVersion,                 // 1
+    /// Number of the live tree; trees before it are archived
+    pub tree_index: u32,                           // 4
+    pub mode: PoolMode,                            // 1
+    /// Unix time each `root_history` entry was recorded
+    pub root_timestamps: [i64; ROOT_HISTORY_SIZE], // 30*8 = 240
+    /// Leaves in the live tree when each `root_history` entry was recorded
+    pub root_leaf_counts: [u32; ROOT_HISTORY_SIZE], // 30*4 = 120
+    /// Deposits that must follow a withdrawal's root (0 = off)
+    pub min_deposits_after: u32,                   // 4
+    /// Seconds that must pass after a withdrawal's root was recorded (0 = off)
+    pub min_withdraw_delay: u32,                   // 4
+    /// Notes paid into the vault and inserted (queued ones count once
+    /// flushed), so not `transfer_note`'s; in shielded pools, `transact` deposits
+    pub total_deposits: u64,                       // 8
+    /// Notes paid out of the vault by `withdraw` and `withdraw_zk`, so unlike
+    /// `withdrawal_count` not `transfer_note`'s, whose value stays in the pool;
+    /// in shielded pools, `transact` withdrawals. Audits compare the vault
+    /// against `total_deposits - total_withdrawals`.
+    pub total_withdrawals: u64,                    // 8
+    /// Shielded pools: lamports the vault owes its notes
+    pub shielded_balance: u64,                     // 8
+    /// Live-tree roots kept for `min_withdraw_delay` beyond the root history,
+    /// oldest first. A root becomes the newest checkpoint once the previous
+    /// one is `min_withdraw_delay` old, so while the newest is too young the
+    /// other is old enough, and a burst of deposits cannot push every root
+    /// that satisfies the delay out of the history.
+    pub delay_checkpoints: [RootCheckpoint; 2],    // 2*44 = 88
+}
+
+/// A recorded root with the data `check_withdrawal_delay` needs.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct RootCheckpoint {
+    pub root: [u8; 32],                            // 32
+    pub recorded_at: i64,                          // 8
+    /// Leaves in the live tree when the root was recorded
+    pub leaf_count: u32,                           // 4
+}
+
+impl Pool {
+    /// Number of leaves this pool's tree can hold.
+    pub fn max_leaves(&self) -> u32 {
+        1u32 << self.tree_depth
+    }
+
+    /// Whether the live tree has no room for another insertion: one leaf in
+    /// fixed pools, a `transact`'s outputs in shielded ones.
+    pub fn is_full(&self) -> bool {
+        let batch = match self.mode {
+            PoolMode::Fixed => 1,
+            PoolMode::Shielded => TRANSACT_OUTPUTS as u32,
+        };
+        self.next_index + batch > self.max_leaves()
+    }
+
+    /// Leaves across all of the pool's trees; archived trees are full.
+    pub fn total_leaves(&self) -> u64 {
+        self.tree_index as u64 * self.max_leaves() as u64 + self.next_index as u64
+    }
+
+    /// Fixed-denomination notes paid in and not yet paid out, excluding queued ones.
+    pub fn outstanding_notes(&self) -> u64 {
+        self.total_deposits.saturating_sub(self.total_withdrawals)
+    }
+
+    /// Reset the tree to empty for the pool's hash function and depth.
+    fn init_tree(&mut self) -> Result<()> {
+        let depth = self.tree_depth as usize;
+        let zh = zero_hashes(self.hash_function, self.tree_version, depth)?;
+        self.current_root = zh[depth - 1];
+        self.root_history = [ZERO_VALUE; ROOT_HISTORY_SIZE];
+        self.root_history[0] = self.current_root;
+        self.root_timestamps = [0; ROOT_HISTORY_SIZE];
+        self.root_timestamps[0] = Clock::get()?.unix_timestamp;
+        self.root_leaf_counts = [0; ROOT_HISTORY_SIZE];
+        self.current_root_index = 0;
+        self.filled_subtrees = vec![self.tree_version.zero_value(); depth];
+        self.filled_subtrees[1..].copy_from_slice(&zh[..depth - 1]);
+        self.reset_checkpoints();
+        Ok(())
+    }
+
+    /// Restart both delay checkpoints at the current root.
+    fn reset_checkpoints(&mut self) {
+        let slot = self.current_root_index as usize;
+        self.delay_checkpoints = [RootCheckpoint {
+            root: self.current_root,
+            recorded_at: self.root_timestamps[slot],
+            leaf_count: self.root_leaf_counts[slot],
+        }; 2];
+    }
+
+    /// Append a commitment to the incremental Merkle tree and record the new
+    /// root. Returns the leaf's index.
+    fn insert_leaf(&mut self, commitment: [u8; 32]) -> Result<u32> {
+        let leaf_index = self.append_leaf(commitment)?;
+        let root = self.current_root;
+        self.push_root(root)?;
+        Ok(leaf_index)
+    }
+
+    /// Append a commitment and update `current_root` without adding it to
+    /// the root history. Returns the leaf's index.
+    fn append_leaf(&mut self, commitment: [u8; 32]) -> Result<u32> {
+        require!(self.next_index < self.max_leaves(), ClawLinkError::MerkleTreeFull);
+
+        let leaf_index = self.next_index;
+        let (hash, version) = (self.hash_function, self.tree_version);
+        let zh = zero_hashes(hash, version, self.tree_depth as usize)?;
+        let mut current_hash = hash_leaf(hash, version, &commitment)?;
+        let mut current_index = leaf_index;
+
+        for i in 0..self.tree_depth as usize {
+            if current_index & 1 == 0 {
+                self.filled_subtrees[i] = current_hash;
+                let zero_at_level = if i == 0 { version.zero_value() } else { zh[i - 1] };
+                current_hash = hash_pair(hash, version, &current_hash, &zero_at_level)?;
+            } else {
+                current_hash = hash_pair(hash, version, &self.filled_subtrees[i], &current_hash)?;
+            }
+            current_index /= 2;
+        }
+
+        self.current_root = current_hash;
+        self.next_index = leaf_index + 1;
+        Ok(leaf_index)
+    }
+
+    /// Compute the root reached from `commitment` at `leaf_index` via its
+    /// sibling path, hashing as tree `version` (archived trees may differ).
+    fn compute_root(
+        &self,
+        version: TreeVersion,
+        commitment: [u8; 32],
+        leaf_index: u32,
+        proof: &[[u8; 32]],
+    ) -> Result<[u8; 32]> {
+        require!(proof.len() == self.tree_depth as usize, ClawLinkError::InvalidProof);
+        let hash = self.hash_function;
+        let mut current_hash = hash_leaf(hash, version, &commitment)?;
+        let mut index = leaf_index;
+        for sibling in proof.iter() {
+            if index & 1 == 0 {
+                current_hash = hash_pair(hash, version, &current_hash, sibling)?;
+            } else {
+                current_hash = hash_pair(hash, version, sibling, &current_hash)?;
+            }
+            index /= 2;
+        }
+        Ok(current_hash)
+    }
+
+    /// Record a new Merkle root in the ring buffer and make it current,
+    /// noting when it was recorded and how many leaves it covers. With a
+    /// withdrawal delay set, it also becomes the newest delay checkpoint once
+    /// the previous one is `min_withdraw_delay` old.
+    pub fn push_root(&mut self, root: [u8; 32]) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let next = (self.current_root_index as usize + 1) % ROOT_HISTORY_SIZE;
+        self.root_history[next] = root;
+        self.root_timestamps[next] = now;
+        self.root_leaf_counts[next] = self.next_index;
+        self.current_root_index = next as u8;
+        self.current_root = root;
+
+        let newest = self.delay_checkpoints[1];
+        if self.min_withdraw_delay > 0
+            && now.saturating_sub(newest.recorded_at) >= self.min_withdraw_delay as i64
+        {
+            self.delay_checkpoints = [
+                newest,
+                RootCheckpoint { root, recorded_at: now, leaf_count: self.next_index },
+            ];
+        }
+        Ok(())
+    }
+
+    /// Whether `root` is one of the last `ROOT_HISTORY_SIZE` roots of this
+    /// pool, or one of its delay checkpoints.
+    pub fn is_known_root(&self, root: &[u8; 32]) -> bool {
+        if *root == ZERO_VALUE {
+            return false;
+        }
+        self.root_history.iter().any(|r| r == root)
+            || self.delay_checkpoints.iter().any(|c| c.root == *root)
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct PoolRegistry {
+    #[max_len(MAX_POOLS)]
+    pub pools: Vec<PoolEntry>,                     // 4 + 64*44 = 2820
+    pub bump: u8,                                  // 1
+}
+
+impl PoolRegistry {
+    /// Registry entry for `pool_id`, if the pool is active.
+    pub fn find(&self, pool_id: u8) -> Option<&PoolEntry> {
+        self.pools.iter().find(|p| p.pool_id == pool_id)
+    }
+}
+
+/// A registered pool's fixed parameters.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, InitSpace)]
+pub struct PoolEntry {
+    pub pool_id: u8,                               // 1
+    pub denomination: u64,                         // 8
+    pub tree_depth: u8,                            // 1
+    pub mint: Option<Pubkey>,                      // 1 + 32
+    pub mode: PoolMode,                            // 1
+}
+
+/// How a pool's notes are valued and spent.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug, InitSpace)]
+pub enum PoolMode {
+    /// Every note is worth the pool's denomination; spent by `withdraw` or `withdraw_zk`
+    Fixed,
+    /// Notes carry hidden amounts; spent and created only by `transact`
+    Shielded,
+}
+
+/// Hash function a pool uses for its tree, commitments and nullifiers.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug, InitSpace)]
+pub enum HashFunction {
+    /// SHA256 — cheap on-chain, expensive inside a SNARK circuit
+    Sha256,
+    /// Poseidon over BN254 (circom-compatible, x^5 S-box, big-endian).
+    /// Every input must be a canonical scalar field element.
+    Poseidon,
+}
+
+impl HashFunction {
+    /// Hash the concatenation of `inputs` (SHA256) or the inputs as field elements (Poseidon).
+    fn hashv(self, inputs: &[&[u8]]) -> Result<[u8; 32]> {
+        match self {
+            HashFunction::Sha256 => Ok(anchor_lang::solana_program::hash::hashv(inputs).to_bytes()),
+            HashFunction::Poseidon => {
+                solana_poseidon::hashv(Parameters::Bn254X5, Endianness::BigEndian, inputs)
+                    .map(|h| h.to_bytes())
+                    .map_err(|_| error!(ClawLinkError::InvalidFieldElement))
+            }
+        }
+    }
+}
+
+/// Merkle tree hashing scheme of a pool.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug, InitSpace)]
+pub enum TreeVersion {
+    /// H(left || right) nodes, raw commitments as leaves, all-zero empty leaf.
+    /// Leaves and nodes are indistinguishable; kept for the trees of pools
+    /// upgraded by `migrate_pool`.
+    Legacy,
+    /// H(NODE_DOMAIN || left || right) nodes, H(LEAF_DOMAIN || commitment)
+    /// leaves, `DOMAIN_ZERO_VALUE` empty leaf
+    DomainSeparated,
+}
+
+impl TreeVersion {
+    /// Value of an empty leaf.
+    fn zero_value(self) -> [u8; 32] {
+        match self {
+            TreeVersion::Legacy => ZERO_VALUE,
+            TreeVersion::DomainSeparated => DOMAIN_ZERO_VALUE,
+        }
+    }
+}
+
+/// Circuit a Groth16 verifying key belongs to; each has its own key PDA.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ZkCircuit {
+    /// `withdraw_zk`: one fixed-denomination note
+    Withdraw,
+    /// `transfer_note`: one fixed-denomination note into a new commitment
+    Transfer,
+    /// `transact`: two-in, two-out join-split in a shielded pool
+    Transact,
+}
+
+impl ZkCircuit {
+    /// Seed of the circuit's verifying key PDA, after `b"verifying_key"`.
+    pub fn seed(self) -> &'static [u8] {
+        match self {
+            ZkCircuit::Withdraw => b"withdraw",
+            ZkCircuit::Transfer => b"transfer",
+            ZkCircuit::Transact => b"transact",
+        }
+    }
+
+    /// Number of public inputs the circuit's proofs carry.
+    pub fn public_inputs(self) -> usize {
+        match self {
+            ZkCircuit::Withdraw => ZK_PUBLIC_INPUTS,
+            ZkCircuit::Transfer => TRANSFER_PUBLIC_INPUTS,
+            ZkCircuit::Transact => TRANSACT_PUBLIC_INPUTS,
+        }
+    }
+}
+
+/// Final state of a full tree, kept so its notes stay withdrawable.
+#[account]
+#[derive(InitSpace)]
+pub struct TreeArchive {
+    pub pool_id: u8,                               // 1
+    pub tree_index: u32,                           // 4
+    /// Root of the full tree
+    pub root: [u8; 32],                            // 32
+    pub tree_version: TreeVersion,                 // 1
+    pub bump: u8,                                  // 1
+    /// Unix time the final root was recorded
+    pub root_recorded_at: i64,                     // 8
+}
+
+/// Deposits waiting for `flush_deposits` to assign their leaf indices.
+#[account]
+#[derive(InitSpace)]
+pub struct DepositQueue {
+    pub pool_id: u8,                               // 1
+    #[max_len(DEPOSIT_QUEUE_SIZE)]
+    pub pending: Vec<QueuedDeposit>,               // 4 + 16*410 = 6564
+    pub bump: u8,                                  // 1
+}
+
+/// A queued commitment and the payloads its `DepositEvent` will carry.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, InitSpace)]
+pub struct QueuedDeposit {
+    pub commitment: [u8; 32],                      // 32
+    pub encrypted_note: Option<EncryptedNote>,     // 1 + 188
+    pub audit_memo: Option<EncryptedNote>,         // 1 + 188
+}
+
+/// A pool's deposit limits and the deposits counted in the current window.
+/// Windows are fixed: the first deposit after one ends starts the next, and
+/// the counts start over.
+#[account]
+#[derive(InitSpace)]
+pub struct DepositWindow {
+    pub pool_id: u8,                               // 1
+    pub bump: u8,                                  // 1
+    /// Window length in seconds (0 = no limits)
+    pub window_seconds: u32,                       // 4
+    /// Deposits per window, from anyone
+    pub max_deposits: u32,                         // 4
+    /// Deposits per window from one depositor (0 = no limit)
+    pub max_per_depositor: u16,                    // 2
+    /// When the current window started
+    pub window_start: i64,                         // 8
+    /// Deposits in the current window
+    pub count: u32,                                // 4
+    /// Deposits per depositor in the current window, kept only while a
+    /// per-depositor limit is on
+    #[max_len(MAX_WINDOW_DEPOSITORS)]
+    pub depositors: Vec<DepositorCount>,           // 4 + 64*34 = 2180
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, InitSpace)]
+pub struct DepositorCount {
+    pub depositor: Pubkey,
+    pub count: u16,
+}
+
+impl DepositWindow {
+    /// Count a deposit at `now` against the limits, starting a new window if
+    /// the current one has ended.
+    pub fn record(&mut self, depositor: Pubkey, now: i64) -> Result<()> {
+        if self.window_seconds == 0 {
+            return Ok(());
+        }
+        if now >= self.window_start.saturating_add(self.window_seconds as i64) {
+            self.window_start = now;
+            self.count = 0;
+            self.depositors.clear();
+        }
+
+        require!(
+            self.count < self.max_deposits,
+            ClawLinkError::PoolDepositLimitReached
+        );
+        if self.max_per_depositor > 0 {
+            match self.depositors.iter_mut().find(|entry| entry.depositor == depositor) {
+                Some(entry) => {
+                    require!(
+                        entry.count < self.max_per_depositor,
+                        ClawLinkError::DepositorLimitReached
+                    );
+                    entry.count += 1;
+                }
+                None => {
+                    require!(
+                        self.depositors.len() < MAX_WINDOW_DEPOSITORS,
+                        ClawLinkError::TooManyWindowDepositors
+                    );
+                    self.depositors.push(DepositorCount { depositor, count: 1 });
+                }
+            }
+        }
+        self.count += 1;
+        Ok(())
+    }
 }
 
 #[account]
@@ -561,8 +4481,14 @@ pub struct CommitmentLeaf {
     pub leaf_index: u32,          // 4
     pub pool_id: u8,              // 1
     pub bump: u8,                 // 1
+    /// Depositor who paid the rent, refunded by `close_leaf`
+    pub payer: Pubkey,            // 32
+    pub tree_index: u32,          // 4
 }
 
+/// Pre-shard record of a spent nullifier, one PDA per nullifier. New spends go
+/// to nullifier shards; existing ones still block their nullifier until
+/// `migrate_nullifier` moves them.
 #[account]
 #[derive(InitSpace)]
 pub struct NullifierAccount {
@@ -571,11 +4497,151 @@ pub struct NullifierAccount {
     pub bump: u8,                 // 1
 }
 
+#[account]
+#[derive(InitSpace)]
+pub struct WithdrawalCommit {
+    pub binding: [u8; 32],       // 32
+    pub slot: u64,                // 8
+    pub bump: u8,                 // 1
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct VerifyingKey {
+    pub alpha_g1: [u8; 64],       // 64
+    pub beta_g2: [u8; 128],       // 128
+    pub gamma_g2: [u8; 128],      // 128
+    pub delta_g2: [u8; 128],      // 128
+    #[max_len(8)]
+    pub ic: Vec<[u8; 64]>,        // 4 + 8*64 = 516 (up to 7 public inputs)
+    pub bump: u8,                 // 1
+}
+
+impl VerifyingKey {
+    /// Borrow the key in the verifier's syscall encoding.
+    fn as_groth16(&self) -> groth16::Groth16VerifyingKey<'_> {
+        groth16::Groth16VerifyingKey {
+            alpha_g1: &self.alpha_g1,
+            beta_g2: &self.beta_g2,
+            gamma_g2: &self.gamma_g2,
+            delta_g2: &self.delta_g2,
+            ic: &self.ic,
+        }
+    }
+}
+
+/// Proof and public note data of a `transact`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct TransactProof {
+    pub proof_a: [u8; 64],
+    pub proof_b: [u8; 128],
+    pub proof_c: [u8; 64],
+    /// Root of the tree holding the input notes
+    pub root: [u8; 32],
+    pub input_nullifiers: [[u8; 32]; TRANSACT_INPUTS],
+    pub output_commitments: [[u8; 32]; TRANSACT_OUTPUTS],
+}
+
+// ─── Events ─────────────────────────────────────────────────────────
+//
+// Emitted through `emit_cpi!`, so they land in the transaction's inner
+// instructions (not logs) and can't be truncated. Each carries Anchor's
+// event discriminator, sha256("event:<Name>")[..8], and is listed in the IDL.
+
+/// A commitment was inserted into a pool's tree.
+#[event]
+pub struct DepositEvent {
+    pub commitment: [u8; 32],
+    pub leaf_index: u32,
+    /// Tree within the pool that `leaf_index` refers to
+    pub tree_index: u32,
+    pub pool_id: u8,
+    /// Recorded tree root covering this leaf: the root right after it was
+    /// inserted, or after the whole batch for `flush_deposits` and `transact`
+    pub root: [u8; 32],
+    pub timestamp: i64,
+    /// Note for the payee, if the depositor attached one
+    pub encrypted_note: Option<EncryptedNote>,
+    /// Audit memo sealed to the depositor's viewing key, if attached
+    pub audit_memo: Option<EncryptedNote>,
+}
+
+/// A payload sealed to an X25519 key: XChaCha20-Poly1305 under
+/// SHA256(X25519(ephemeral_secret, recipient_key)). For notes the key is the
+/// payee's encryption key and the plaintext secret || nullifier_preimage
+/// [|| memo]; audit memos use the same envelope under a viewing key. The
+/// one-time key keeps the depositor anonymous; nothing in it names the
+/// recipient.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, InitSpace)]
+pub struct EncryptedNote {
+    /// Depositor's one-time X25519 public key
+    pub ephemeral_key: [u8; 32],
+    pub nonce: [u8; 24],
+    #[max_len(MAX_ENCRYPTED_NOTE_LEN)]
+    pub ciphertext: Vec<u8>,
+}
+
+/// A note was spent and paid out.
+#[event]
+pub struct WithdrawEvent {
+    pub nullifier_hash: [u8; 32],
+    pub recipient: Pubkey,
+    pub relayer: Pubkey,
+    pub fee: u64,
+}
+
+/// A note was spent into a new commitment in the same pool. The new note
+/// gets its own `DepositEvent`.
+#[event]
+pub struct TransferEvent {
+    pub pool_id: u8,
+    pub nullifier_hash: [u8; 32],
+}
+
+/// Notes of a shielded pool were spent and replaced. The outputs get their
+/// own `DepositEvent`s.
+#[event]
+pub struct TransactEvent {
+    pub pool_id: u8,
+    pub input_nullifiers: [[u8; 32]; TRANSACT_INPUTS],
+    /// SOL deposited (positive) or withdrawn to `recipient` (negative)
+    pub ext_amount: i64,
+    pub fee: u64,
+    pub recipient: Pubkey,
+    pub relayer: Pubkey,
+}
+
+/// Result of `audit_pool`. Monitoring should alert on anything but `Balanced`.
+#[event]
+pub struct PoolAuditEvent {
+    pub pool_id: u8,
+    pub total_deposits: u64,
+    pub total_withdrawals: u64,
+    /// Fixed pools: notes paid in and not yet out, including queued ones
+    pub outstanding_notes: u64,
+    /// What the vault owes its notes (lamports, or tokens for SPL-token pools)
+    pub expected: u64,
+    /// What the vault holds, in the same unit
+    pub held: u64,
+    pub status: AuditStatus,
+    pub timestamp: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuditStatus {
+    /// The vault covers its notes, give or take its rent-exempt minimum
+    Balanced,
+    /// The vault holds less than its notes are owed
+    Insolvent,
+    /// The vault holds more than expected, e.g. after a stray transfer
+    Surplus,
+}
+
 // ─── Errors ─────────────────────────────────────────────────────────
 
 #[error_code]
 pub enum ClawLinkError {
-    #[msg("Invalid pool ID (must be 0, 1, or 2)")]
+    #[msg("Pool is not in the registry")]
     InvalidPool,
     #[msg("Merkle tree is full")]
     MerkleTreeFull,
@@ -593,4 +4659,90 @@ pub enum ClawLinkError {
     Unauthorized,
     #[msg("Arithmetic overflow")]
     Overflow,
+    #[msg("Merkle root is not in the pool's recent root history")]
+    UnknownRoot,
+    #[msg("Relayer fee exceeds the configured maximum")]
+    RelayerFeeTooHigh,
+    #[msg("Public input is not a canonical BN254 scalar field element")]
+    InvalidPublicInput,
+    #[msg("Verifying key does not match the expected number of public inputs")]
+    InvalidVerifyingKey,
+    #[msg("Value is not a canonical BN254 scalar field element")]
+    InvalidFieldElement,
+    #[msg("Withdrawal does not match a commit for this note and recipient")]
+    WithdrawalNotCommitted,
+    #[msg("Withdrawal commit must be from an earlier slot")]
+    CommitTooRecent,
+    #[msg("Treasury balance is too low")]
+    InsufficientTreasuryBalance,
+    #[msg("Treasury must be drained before closing the config")]
+    TreasuryNotEmpty,
+    #[msg("Pool is sunset and no longer accepts deposits")]
+    PoolSunset,
+    #[msg("Pool still has outstanding notes or vault funds")]
+    PoolNotDrained,
+    #[msg("Pool ID is already registered")]
+    PoolAlreadyRegistered,
+    #[msg("Pool registry is full")]
+    RegistryFull,
+    #[msg("Denomination must be greater than zero")]
+    InvalidDenomination,
+    #[msg("Tree depth must be between 1 and 20")]
+    InvalidTreeDepth,
+    #[msg("SPL-token pool requires its token accounts")]
+    MissingTokenAccounts,
+    #[msg("Deposit queue is full; flush it first")]
+    DepositQueueFull,
+    #[msg("Deposit queue is empty")]
+    DepositQueueEmpty,
+    #[msg("Leaf account must be passed exactly when the pool stores leaf accounts")]
+    LeafAccountMismatch,
+    #[msg("Pool still stores leaf accounts")]
+    LeafStillRequired,
+    #[msg("Commitment has already been deposited")]
+    DuplicateCommitment,
+    #[msg("Tree can only roll over once it is full")]
+    TreeNotFull,
+    #[msg("Archived tree account required for this tree index")]
+    MissingTreeArchive,
+    #[msg("Instruction is not available for this pool's mode")]
+    InvalidPoolMode,
+    #[msg("Deposits require the CLINK fee accounts")]
+    MissingFeeAccounts,
+    #[msg("Encrypted note is too long")]
+    EncryptedNoteTooLong,
+    #[msg("Too few deposits since this root; prove against an older root or wait")]
+    AnonymitySetTooSmall,
+    #[msg("Root is too recent for the pool's withdrawal delay")]
+    WithdrawalTooEarly,
+    #[msg("Disclosed note does not match the deposit or withdrawal")]
+    DisclosureMismatch,
+    #[msg("Deposits are paused")]
+    DepositsPaused,
+    #[msg("Withdrawals and note spends are paused")]
+    WithdrawalsPaused,
+    #[msg("No authority transfer has been proposed")]
+    NoPendingAuthority,
+    #[msg("Signer set needs up to 10 distinct keys and a threshold between 1 and the key count")]
+    InvalidSignerSet,
+    #[msg("Not enough of the authority's signers signed")]
+    NotEnoughSigners,
+    #[msg("Pool has reached its deposit limit for this window")]
+    PoolDepositLimitReached,
+    #[msg("Depositor has reached their deposit limit for this window")]
+    DepositorLimitReached,
+    #[msg("Deposit limits need a window and at least 1 max deposit")]
+    InvalidDepositLimits,
+    #[msg("Account is not in its pre-upgrade layout")]
+    NotLegacyAccount,
+    #[msg("Minimum deposits after a root must be below the root history size (30)")]
+    InvalidWithdrawalDelay,
+    #[msg("Shielded pools must use the Poseidon hash")]
+    InvalidHashFunction,
+    #[msg("Guardian must be a wallet key, not a PDA")]
+    InvalidGuardian,
+    #[msg("Deposit window already counts 64 depositors")]
+    TooManyWindowDepositors,
+    #[msg("Groth16 proofs only work in Poseidon pools")]
+    ProofNeedsPoseidonPool,
 }
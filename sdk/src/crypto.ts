@@ -4,7 +4,13 @@ import { x25519 } from "@noble/curves/ed25519";
 import { xchacha20poly1305 } from "@noble/ciphers/chacha";
 import { sha256 } from "@noble/hashes/sha256";
 import { randomBytes } from "@noble/ciphers/webcrypto";
-import type { ClawLinkMessage } from "./types";
+import type {
+  AuditOpening,
+  ClawLinkMessage,
+  EncryptedNote,
+  PaymentNote,
+  ViewingKey,
+} from "./types";
 
 /**
  * Converts an Ed25519 private key (seed) to an X25519 private key.
@@ -47,6 +53,103 @@ function edPublicToX25519(edPublicKey: Uint8Array): Uint8Array {
   return bytes;
 }
 
+/**
+ * Seal bytes to an X25519 public key with a one-time sender key:
+ * XChaCha20-Poly1305 under SHA256(X25519(ephemeral, recipient)).
+ */
+function seal(recipientX25519PubKey: Uint8Array, plaintext: Uint8Array): EncryptedNote {
+  const ephemeralSecret = x25519.utils.randomPrivateKey();
+  const sharedSecret = x25519.getSharedSecret(
+    ephemeralSecret,
+    recipientX25519PubKey
+  );
+  const encKey = sha256(sharedSecret);
+  const nonce = randomBytes(24);
+
+  return {
+    ephemeralKey: x25519.getPublicKey(ephemeralSecret),
+    nonce,
+    ciphertext: xchacha20poly1305(encKey, nonce).encrypt(plaintext),
+  };
+}
+
+/**
+ * Open a payload sealed with `seal`; null if it was sealed to another key.
+ */
+function open(x25519PrivateKey: Uint8Array, sealed: EncryptedNote): Uint8Array | null {
+  const sharedSecret = x25519.getSharedSecret(x25519PrivateKey, sealed.ephemeralKey);
+  const decKey = sha256(sharedSecret);
+  try {
+    return xchacha20poly1305(decKey, sealed.nonce).decrypt(sealed.ciphertext);
+  } catch {
+    return null;
+  }
+}
+
+/**
+ * Encrypt a payment note to a payee's X25519 key (their on-chain
+ * `AgentProfile.encryptionKey`), for `deposit`'s `encryptedNote` argument.
+ * Uses a one-time X25519 key, so the payload doesn't identify the depositor.
+ */
+export function encryptNote(
+  recipientX25519PubKey: Uint8Array,
+  note: PaymentNote
+): EncryptedNote {
+  const memo = note.memo ?? new Uint8Array(0);
+  const plaintext = new Uint8Array(64 + memo.length);
+  plaintext.set(note.secret, 0);
+  plaintext.set(note.nullifierPreimage, 32);
+  plaintext.set(memo, 64);
+  return seal(recipientX25519PubKey, plaintext);
+}
+
+/**
+ * Generate a viewing key for audit memos. Keep `secretKey` (or hand it to
+ * an auditor); pass `publicKey` to `sealAuditMemo`.
+ */
+export function generateViewingKey(): ViewingKey {
+  const secretKey = x25519.utils.randomPrivateKey();
+  return { secretKey, publicKey: x25519.getPublicKey(secretKey) };
+}
+
+/**
+ * Seal a note's opening to a viewing key, for `deposit`'s `auditMemo`
+ * argument: secret || nullifierPreimage || poolId || memo. The program's
+ * `disclosure::AuditOpening` reads the same layout.
+ */
+export function sealAuditMemo(
+  viewingPublicKey: Uint8Array,
+  opening: AuditOpening
+): EncryptedNote {
+  const memo = opening.memo ?? new Uint8Array(0);
+  const plaintext = new Uint8Array(65 + memo.length);
+  plaintext.set(opening.secret, 0);
+  plaintext.set(opening.nullifierPreimage, 32);
+  plaintext[64] = opening.poolId;
+  plaintext.set(memo, 65);
+  return seal(viewingPublicKey, plaintext);
+}
+
+/**
+ * Open the audit memo of a `DepositEvent` with a viewing key's secret.
+ * Returns null when the memo was sealed to a different viewing key. The
+ * opening is only a claim until its commitment and nullifier hash are
+ * recomputed and matched to the deposit and withdrawal.
+ */
+export function openAuditMemo(
+  viewingSecretKey: Uint8Array,
+  sealed: EncryptedNote
+): AuditOpening | null {
+  const plaintext = open(viewingSecretKey, sealed);
+  if (plaintext === null || plaintext.length < 65) return null;
+  return {
+    secret: plaintext.slice(0, 32),
+    nullifierPreimage: plaintext.slice(32, 64),
+    poolId: plaintext[64],
+    memo: plaintext.length > 65 ? plaintext.slice(65) : undefined,
+  };
+}
+
 /**
  * Encryption and signing utilities for ClawLink messaging.
  */
@@ -156,6 +259,22 @@ export class ClawLinkCrypto {
     return new TextDecoder().decode(plaintext);
   }
 
+  /**
+   * Try to decrypt a note attached to a `DepositEvent`.
+   * Returns null when the note was encrypted to someone else, so callers
+   * can scan every deposit event with their own key.
+   */
+  decryptNote(encrypted: EncryptedNote): PaymentNote | null {
+    const plaintext = open(this.x25519PrivateKey, encrypted);
+    if (plaintext === null || plaintext.length < 64) return null;
+
+    return {
+      secret: plaintext.slice(0, 32),
+      nullifierPreimage: plaintext.slice(32, 64),
+      memo: plaintext.length > 64 ? plaintext.slice(64) : undefined,
+    };
+  }
+
   /**
    * Derive X25519 public key from a Solana ed25519 public key.
    * Useful for looking up encryption keys without on-chain data.
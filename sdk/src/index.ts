@@ -1,3 +1,16 @@
 export { ClawLinkClient } from "./client";
-export { ClawLinkCrypto } from "./crypto";
-export type { ClawLinkMessage, AgentProfile } from "./types";
+export {
+  ClawLinkCrypto,
+  encryptNote,
+  generateViewingKey,
+  sealAuditMemo,
+  openAuditMemo,
+} from "./crypto";
+export type {
+  ClawLinkMessage,
+  AgentProfile,
+  PaymentNote,
+  EncryptedNote,
+  AuditOpening,
+  ViewingKey,
+} from "./types";
@@ -24,3 +24,42 @@ export interface AgentProfile {
   messageCount: number;
   bump: number;
 }
+
+/**
+ * Payment note: everything needed to withdraw a deposit.
+ */
+export interface PaymentNote {
+  secret: Uint8Array; // 32 bytes
+  nullifierPreimage: Uint8Array; // 32 bytes
+  memo?: Uint8Array; // optional free-form bytes
+}
+
+/**
+ * A payload sealed to an X25519 key: a note encrypted to its payee
+ * (`DepositEvent.encryptedNote`) or an audit memo (`DepositEvent.auditMemo`).
+ */
+export interface EncryptedNote {
+  ephemeralKey: Uint8Array; // 32-byte one-time X25519 public key
+  nonce: Uint8Array; // 24 bytes
+  ciphertext: Uint8Array; // XChaCha20-Poly1305(secret || nullifierPreimage || [poolId ||] memo)
+}
+
+/**
+ * What an audit memo discloses: the note's opening and the pool it was
+ * deposited into, so the viewing key holder can recompute its commitment
+ * and nullifier hash and match them to the deposit and withdrawal.
+ */
+export interface AuditOpening {
+  poolId: number;
+  secret: Uint8Array; // 32 bytes
+  nullifierPreimage: Uint8Array; // 32 bytes
+  memo?: Uint8Array; // optional free-form bytes, up to 47
+}
+
+/**
+ * X25519 viewing key for audit memos.
+ */
+export interface ViewingKey {
+  secretKey: Uint8Array; // 32 bytes, decrypts audit memos
+  publicKey: Uint8Array; // 32 bytes, memos are sealed to it
+}
@@ -1,25 +1,52 @@
 import * as anchor from "@coral-xyz/anchor";
 import { Program, BN } from "@coral-xyz/anchor";
-import { ClawCashProtocol } from "../target/types/claw_cash_protocol";
+import { ClawLinkPayments } from "../target/types/claw_link_payments";
 import {
   Keypair,
   PublicKey,
   SystemProgram,
   LAMPORTS_PER_SOL,
+  Transaction,
+  ComputeBudgetProgram,
+  AddressLookupTableAccount,
+  AddressLookupTableProgram,
+  TransactionMessage,
+  VersionedTransaction,
 } from "@solana/web3.js";
 import {
   createMint,
+  getAccount,
+  getMint,
   getOrCreateAssociatedTokenAccount,
   mintTo,
   TOKEN_PROGRAM_ID,
 } from "@solana/spl-token";
 import { createHash } from "crypto";
 import { expect } from "chai";
+import {
+  ClawLinkCrypto,
+  encryptNote,
+  generateViewingKey,
+  openAuditMemo,
+  sealAuditMemo,
+} from "../sdk/src/crypto";
+import zkVectors from "./fixtures/withdraw_zk.json";
+import transactVectors from "./fixtures/transact.json";
+import transferVectors from "./fixtures/transfer_note.json";
+import legacyVectors from "./fixtures/legacy.json";
 
 // ─── Constants ────────────────────────────────────────────────────────
 
 const MERKLE_TREE_DEPTH = 20;
-const ZERO_VALUE = Buffer.alloc(32, 0);
+
+// Domain-separated trees: SHA256("claw-link") mod r as the empty leaf,
+// big-endian tags 1 / 2 in front of leaves / nodes
+const ZERO_VALUE = Buffer.from(
+  "170e4d6027017bf54e0f9de538c797b3a85d2045d7cb3650904ef0dfc7fb3ee0",
+  "hex"
+);
+const LEAF_DOMAIN = Buffer.concat([Buffer.alloc(31, 0), Buffer.from([1])]);
+const NODE_DOMAIN = Buffer.concat([Buffer.alloc(31, 0), Buffer.from([2])]);
 
 const POOL_DENOMINATIONS = [
   0.1 * LAMPORTS_PER_SOL, // Pool 0: 0.1 SOL
@@ -38,15 +65,20 @@ function sha256(data: Buffer): Buffer {
 
 /** Hash two 32-byte nodes together for the Merkle tree */
 function hashPair(left: Buffer, right: Buffer): Buffer {
-  const combined = Buffer.concat([left, right]);
+  const combined = Buffer.concat([NODE_DOMAIN, left, right]);
   return sha256(combined);
 }
 
+/** Hash a commitment into the leaf stored in the Merkle tree */
+function hashLeaf(commitment: Buffer): Buffer {
+  return sha256(Buffer.concat([LEAF_DOMAIN, commitment]));
+}
+
 /** Precompute zero hashes for each level of the Merkle tree */
-function zeroHashes(): Buffer[] {
-  const zh: Buffer[] = new Array(MERKLE_TREE_DEPTH);
+function zeroHashes(depth = MERKLE_TREE_DEPTH): Buffer[] {
+  const zh: Buffer[] = new Array(depth);
   zh[0] = hashPair(ZERO_VALUE, ZERO_VALUE);
-  for (let i = 1; i < MERKLE_TREE_DEPTH; i++) {
+  for (let i = 1; i < depth; i++) {
     zh[i] = hashPair(zh[i - 1], zh[i - 1]);
   }
   return zh;
@@ -87,9 +119,10 @@ function generateNote(): Note {
  */
 function computeMerkleProof(
   leaves: Buffer[],
-  targetIndex: number
+  targetIndex: number,
+  depth = MERKLE_TREE_DEPTH
 ): Buffer[] {
-  const zh = zeroHashes();
+  const zh = zeroHashes(depth);
   const proof: Buffer[] = [];
 
   // Build tree layer by layer
@@ -97,12 +130,12 @@ function computeMerkleProof(
   // We only need to track nodes that differ from the zero pattern
   let currentLayer = new Map<number, Buffer>();
   for (let i = 0; i < leaves.length; i++) {
-    currentLayer.set(i, leaves[i]);
+    currentLayer.set(i, hashLeaf(leaves[i]));
   }
 
   let pathIndex = targetIndex;
 
-  for (let level = 0; level < MERKLE_TREE_DEPTH; level++) {
+  for (let level = 0; level < depth; level++) {
     // Get the sibling of pathIndex at this level
     const siblingIndex = pathIndex % 2 === 0 ? pathIndex + 1 : pathIndex - 1;
 
@@ -152,23 +185,26 @@ function computeMerkleProof(
  * Compute the Merkle root from leaves, matching the on-chain incremental insertion.
  * This inserts leaves one at a time using the same algorithm as the program.
  */
-function computeIncrementalRoot(leaves: Buffer[]): Buffer {
-  const zh = zeroHashes();
+function computeIncrementalRoot(
+  leaves: Buffer[],
+  depth = MERKLE_TREE_DEPTH
+): Buffer {
+  const zh = zeroHashes(depth);
 
   // Start with the zero root
-  let filledSubtrees: Buffer[] = new Array(MERKLE_TREE_DEPTH);
+  let filledSubtrees: Buffer[] = new Array(depth);
   filledSubtrees[0] = ZERO_VALUE;
-  for (let i = 1; i < MERKLE_TREE_DEPTH; i++) {
+  for (let i = 1; i < depth; i++) {
     filledSubtrees[i] = zh[i - 1];
   }
-  let currentRoot = zh[MERKLE_TREE_DEPTH - 1];
+  let currentRoot = zh[depth - 1];
 
   // Insert each leaf
   for (let leafIdx = 0; leafIdx < leaves.length; leafIdx++) {
-    let currentHash = leaves[leafIdx];
+    let currentHash = hashLeaf(leaves[leafIdx]);
     let currentIndex = leafIdx;
 
-    for (let i = 0; i < MERKLE_TREE_DEPTH; i++) {
+    for (let i = 0; i < depth; i++) {
       if (currentIndex % 2 === 0) {
         filledSubtrees[i] = currentHash;
         const zeroAtLevel = i === 0 ? ZERO_VALUE : zh[i - 1];
@@ -208,12 +244,15 @@ function getVaultPDA(
 function getLeafPDA(
   programId: PublicKey,
   poolId: number,
-  leafIndex: number
+  leafIndex: number,
+  treeIndex = 0
 ): [PublicKey, number] {
+  const treeIndexBuf = Buffer.alloc(4);
+  treeIndexBuf.writeUInt32LE(treeIndex);
   const leafIndexBuf = Buffer.alloc(4);
   leafIndexBuf.writeUInt32LE(leafIndex);
   return PublicKey.findProgramAddressSync(
-    [Buffer.from("leaf"), Buffer.from([poolId]), leafIndexBuf],
+    [Buffer.from("leaf"), Buffer.from([poolId]), treeIndexBuf, leafIndexBuf],
     programId
   );
 }
@@ -229,11 +268,68 @@ function getNullifierPDA(
   );
 }
 
+/** Derive the shard PDA a nullifier is recorded in (keyed by its last byte) */
+function getNullifierShardPDA(
+  programId: PublicKey,
+  nullifierHash: Buffer
+): [PublicKey, number] {
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from("nullifier_shard"), nullifierHash.subarray(31)],
+    programId
+  );
+}
+
+/** Hex string → byte array for instruction arguments */
+function hexBytes(hex: string): number[] {
+  return Array.from(Buffer.from(hex, "hex"));
+}
+
+/** Derive a PDA for a withdrawal commit */
+function getWithdrawCommitPDA(
+  programId: PublicKey,
+  binding: Buffer
+): [PublicKey, number] {
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from("withdraw_commit"), binding],
+    programId
+  );
+}
+
+/**
+ * Withdrawal binding =
+ *   SHA256(secret || nullifier_preimage || recipient || relayer || fee_le)
+ */
+function withdrawalBinding(
+  secret: Buffer,
+  nullifierPreimage: Buffer,
+  recipient: PublicKey,
+  relayer: PublicKey,
+  fee: number
+): Buffer {
+  return sha256(
+    Buffer.concat([
+      secret,
+      nullifierPreimage,
+      recipient.toBuffer(),
+      relayer.toBuffer(),
+      new BN(fee).toArrayLike(Buffer, "le", 8),
+    ])
+  );
+}
+
 /** Derive config PDA */
 function getConfigPDA(programId: PublicKey): [PublicKey, number] {
   return PublicKey.findProgramAddressSync([Buffer.from("config")], programId);
 }
 
+/** Derive pool registry PDA */
+function getRegistryPDA(programId: PublicKey): [PublicKey, number] {
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from("registry")],
+    programId
+  );
+}
+
 /** Derive treasury PDA */
 function getTreasuryPDA(programId: PublicKey): [PublicKey, number] {
   return PublicKey.findProgramAddressSync(
@@ -242,6 +338,17 @@ function getTreasuryPDA(programId: PublicKey): [PublicKey, number] {
   );
 }
 
+/** Derive the verifying key PDA of a circuit ("withdraw" or "transact") */
+function getVerifyingKeyPDA(
+  programId: PublicKey,
+  circuit: string
+): [PublicKey, number] {
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from("verifying_key"), Buffer.from(circuit)],
+    programId
+  );
+}
+
 // ─── Tests ────────────────────────────────────────────────────────────
 
 describe("Claw Cash Protocol v2", () => {
@@ -249,13 +356,212 @@ describe("Claw Cash Protocol v2", () => {
   anchor.setProvider(provider);
 
   const program = anchor.workspace
-    .clawCashProtocol as Program<ClawCashProtocol>;
+    .clawLinkPayments as Program<ClawLinkPayments>;
   const authority = provider.wallet as anchor.Wallet;
 
-  let clawcashMint: PublicKey;
-  let depositorClawcashAccount: PublicKey;
+  let clinkMint: PublicKey;
+  let depositorClinkAccount: PublicKey;
   const programId = program.programId;
 
+  /** Commit a withdrawal and wait a slot so the reveal is accepted */
+  async function commitWithdrawal(
+    secret: Buffer,
+    nullifierPreimage: Buffer,
+    recipient: PublicKey,
+    relayer: PublicKey = authority.publicKey,
+    fee = 0
+  ): Promise<PublicKey> {
+    const binding = withdrawalBinding(
+      secret,
+      nullifierPreimage,
+      recipient,
+      relayer,
+      fee
+    );
+    await program.methods
+      .commitWithdrawal(Array.from(binding) as any)
+      .accounts({ payer: authority.publicKey })
+      .rpc();
+
+    const committedAt = await provider.connection.getSlot("confirmed");
+    while ((await provider.connection.getSlot("confirmed")) <= committedAt) {
+      await new Promise((resolve) => setTimeout(resolve, 200));
+    }
+    return getWithdrawCommitPDA(programId, binding)[0];
+  }
+
+  /** Decode the `emit_cpi!` events in a confirmed transaction */
+  async function cpiEvents(signature: string) {
+    const tx = await provider.connection.getTransaction(signature, {
+      commitment: "confirmed",
+      maxSupportedTransactionVersion: 0,
+    });
+    const keys = tx!.transaction.message.staticAccountKeys;
+    return (tx!.meta!.innerInstructions ?? [])
+      .flatMap((inner) => inner.instructions)
+      .filter((ix) => keys[ix.programIdIndex].equals(programId))
+      .map((ix) => {
+        // Strip the 8-byte event-instruction tag, keep discriminator + data
+        const data = anchor.utils.bytes.bs58.decode(ix.data).subarray(8);
+        return program.coder.events.decode(
+          anchor.utils.bytes.base64.encode(Buffer.from(data))
+        );
+      })
+      .filter((event) => event !== null);
+  }
+
+  /**
+   * Register pool `poolId` and initialize its tree. A null `denomination`
+   * registers a shielded pool.
+   */
+  async function createPool(
+    poolId: number,
+    denomination: number | null,
+    depth: number,
+    hashFunction: "sha256" | "poseidon" = "sha256",
+    mint: PublicKey | null = null
+  ) {
+    await (denomination === null
+      ? program.methods.registerShieldedPool(poolId, depth)
+      : program.methods.registerPool(poolId, new BN(denomination), depth, mint)
+    )
+      .accounts({ authority: authority.publicKey })
+      .rpc();
+    await program.methods
+      .initializePool(poolId, { [hashFunction]: {} } as any)
+      .accounts({ authority: authority.publicKey })
+      .rpc();
+  }
+
+  /** Store a fixture's Groth16 verifying key for `kind` proofs */
+  async function initializeVerifyingKey(
+    kind: "withdraw" | "transfer" | "transact",
+    vk: typeof transactVectors.verifyingKey
+  ) {
+    await program.methods
+      .initializeVerifyingKey(
+        { [kind]: {} } as any,
+        hexBytes(vk.alphaG1) as any,
+        hexBytes(vk.betaG2) as any,
+        hexBytes(vk.gammaG2) as any,
+        hexBytes(vk.deltaG2) as any,
+        vk.ic.map((p) => hexBytes(p)) as any
+      )
+      .accountsPartial({
+        verifyingKey: getVerifyingKeyPDA(programId, kind)[0],
+        authority: authority.publicKey,
+      })
+      .rpc();
+  }
+
+  /** Deposit a note at `leafIndex` of tree `treeIndex` in a pool that stores leaves */
+  async function depositNote(
+    note: Pick<Note, "commitment">,
+    poolId: number,
+    leafIndex: number,
+    treeIndex = 0
+  ): Promise<string> {
+    return program.methods
+      .deposit(Array.from(note.commitment) as any, poolId, leafIndex, null, null)
+      .accountsPartial({
+        depositorClink: depositorClinkAccount,
+        depositor: authority.publicKey,
+        commitmentLeaf: getLeafPDA(programId, poolId, leafIndex, treeIndex)[0],
+      })
+      .rpc();
+  }
+
+  /**
+   * Commit and withdraw `note` from leaf `leafIndex` of tree `treeIndex` to a
+   * fresh recipient, proving against the tree over `leaves`; returns the
+   * recipient. Archived trees are passed with their archive account.
+   */
+  async function withdrawNote(
+    note: Note,
+    poolId: number,
+    leaves: Buffer[],
+    leafIndex: number,
+    depth = MERKLE_TREE_DEPTH,
+    treeIndex = 0
+  ): Promise<PublicKey> {
+    const [poolPDA] = getPoolPDA(programId, poolId);
+    const pool = await program.account.pool.fetch(poolPDA);
+    const treeArchive =
+      treeIndex < pool.treeIndex
+        ? PublicKey.findProgramAddressSync(
+            [
+              Buffer.from("tree"),
+              Buffer.from([poolId]),
+              new BN(treeIndex).toArrayLike(Buffer, "le", 4),
+            ],
+            programId
+          )[0]
+        : null;
+
+    const recipient = Keypair.generate();
+    const withdrawalCommit = await commitWithdrawal(
+      note.secret,
+      note.nullifierPreimage,
+      recipient.publicKey
+    );
+    await program.methods
+      .withdraw(
+        Array.from(note.secret) as any,
+        Array.from(note.nullifierPreimage) as any,
+        Array.from(note.nullifierHash) as any,
+        treeIndex,
+        leafIndex,
+        computeMerkleProof(leaves, leafIndex, depth).map((p) => Array.from(p) as any),
+        new BN(0)
+      )
+      .accountsPartial({
+        nullifierShard: getNullifierShardPDA(programId, note.nullifierHash)[0],
+        pool: poolPDA,
+        treeArchive,
+        withdrawalCommit,
+        recipient: recipient.publicKey,
+        relayer: authority.publicKey,
+        payer: authority.publicKey,
+      })
+      .rpc();
+    return recipient.publicKey;
+  }
+
+  /** Flush a pool's queue, passing the leaf accounts the flush creates */
+  async function flushDeposits(poolId: number, maxCount = 8): Promise<string> {
+    const pool = await program.account.pool.fetch(getPoolPDA(programId, poolId)[0]);
+    const [queuePDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("deposit_queue"), Buffer.from([poolId])],
+      programId
+    );
+    const queue = await program.account.depositQueue.fetch(queuePDA);
+    const count = pool.storeLeafAccounts
+      ? Math.min(queue.pending.length, maxCount)
+      : 0;
+    const leaves = Array.from({ length: count }, (_, i) => ({
+      pubkey: getLeafPDA(programId, poolId, pool.nextIndex + i, pool.treeIndex)[0],
+      isSigner: false,
+      isWritable: true,
+    }));
+    return program.methods
+      .flushDeposits(poolId, maxCount)
+      .accounts({ payer: authority.publicKey })
+      .remainingAccounts(leaves)
+      .rpc({ commitment: "confirmed" });
+  }
+
+  /** Whether a nullifier is recorded in its shard (sorted 32-byte entries) */
+  async function isSpent(nullifierHash: Buffer): Promise<boolean> {
+    const shard = await provider.connection.getAccountInfo(
+      getNullifierShardPDA(programId, nullifierHash)[0]
+    );
+    if (shard === null) return false;
+    for (let i = 0; i < shard.data.length; i += 32) {
+      if (shard.data.subarray(i, i + 32).equals(nullifierHash)) return true;
+    }
+    return false;
+  }
+
   // Track notes for withdrawal tests
   const pool0Notes: Note[] = [];
   const pool0Leaves: Buffer[] = [];
@@ -271,7 +577,7 @@ describe("Claw Cash Protocol v2", () => {
     await provider.connection.confirmTransaction(sig, "confirmed");
 
     // Create mock CLAWCASH SPL token mint (6 decimals)
-    clawcashMint = await createMint(
+    clinkMint = await createMint(
       provider.connection,
       authority.payer,
       authority.publicKey,
@@ -283,17 +589,17 @@ describe("Claw Cash Protocol v2", () => {
     const ata = await getOrCreateAssociatedTokenAccount(
       provider.connection,
       authority.payer,
-      clawcashMint,
+      clinkMint,
       authority.publicKey
     );
-    depositorClawcashAccount = ata.address;
+    depositorClinkAccount = ata.address;
 
     // Mint plenty of CLAWCASH for fees
     await mintTo(
       provider.connection,
       authority.payer,
-      clawcashMint,
-      depositorClawcashAccount,
+      clinkMint,
+      depositorClinkAccount,
       authority.publicKey,
       10_000_000_000 // 10,000 CLAWCASH
     );
@@ -309,7 +615,7 @@ describe("Claw Cash Protocol v2", () => {
       await program.methods
         .initialize(new BN(FEE_AMOUNT))
         .accounts({
-          clawcashMint: clawcashMint,
+          clinkMint: clinkMint,
           authority: authority.publicKey,
         })
         .rpc();
@@ -319,11 +625,27 @@ describe("Claw Cash Protocol v2", () => {
       expect(config.authority.toBase58()).to.equal(
         authority.publicKey.toBase58()
       );
-      expect(config.clawcashMint.toBase58()).to.equal(
-        clawcashMint.toBase58()
+      expect(config.clinkMint.toBase58()).to.equal(
+        clinkMint.toBase58()
       );
       expect(config.feeAmount.toNumber()).to.equal(FEE_AMOUNT);
       expect(config.treasury.toBase58()).to.equal(treasuryPDA.toBase58());
+      expect(config.maxRelayerFeeBps).to.equal(500);
+    });
+
+    it("initializes the pool registry with the default pools", async () => {
+      await program.methods
+        .initializeRegistry()
+        .accounts({ authority: authority.publicKey })
+        .rpc();
+
+      const [registryPDA] = getRegistryPDA(programId);
+      const registry = await program.account.poolRegistry.fetch(registryPDA);
+      expect(registry.pools.map((p: any) => p.denomination.toNumber())).to.deep.equal(
+        POOL_DENOMINATIONS
+      );
+      expect(registry.pools.every((p: any) => p.treeDepth === MERKLE_TREE_DEPTH))
+        .to.be.true;
     });
   });
 
@@ -332,7 +654,7 @@ describe("Claw Cash Protocol v2", () => {
   describe("initialize_pool", () => {
     it("initializes pool 0 (0.1 SOL)", async () => {
       await program.methods
-        .initializePool(0)
+        .initializePool(0, { sha256: {} })
         .accounts({
           authority: authority.publicKey,
         })
@@ -344,11 +666,12 @@ describe("Claw Cash Protocol v2", () => {
       expect(pool.denomination.toNumber()).to.equal(POOL_DENOMINATIONS[0]);
       expect(pool.nextIndex).to.equal(0);
       expect(pool.filledSubtrees.length).to.equal(MERKLE_TREE_DEPTH);
+      expect(pool.hashFunction).to.deep.equal({ sha256: {} });
     });
 
     it("initializes pool 1 (1 SOL)", async () => {
       await program.methods
-        .initializePool(1)
+        .initializePool(1, { sha256: {} })
         .accounts({
           authority: authority.publicKey,
         })
@@ -361,9 +684,9 @@ describe("Claw Cash Protocol v2", () => {
       expect(pool.nextIndex).to.equal(0);
     });
 
-    it("initializes pool 2 (10 SOL)", async () => {
+    it("initializes pool 2 (10 SOL) with a Poseidon tree", async () => {
       await program.methods
-        .initializePool(2)
+        .initializePool(2, { poseidon: {} })
         .accounts({
           authority: authority.publicKey,
         })
@@ -374,6 +697,7 @@ describe("Claw Cash Protocol v2", () => {
       expect(pool.poolId).to.equal(2);
       expect(pool.denomination.toNumber()).to.equal(POOL_DENOMINATIONS[2]);
       expect(pool.nextIndex).to.equal(0);
+      expect(pool.hashFunction).to.deep.equal({ poseidon: {} });
     });
   });
 
@@ -395,10 +719,12 @@ describe("Claw Cash Protocol v2", () => {
         .deposit(
           Array.from(note.commitment) as any,
           0,
-          0
+          0,
+          null,
+          null
         )
         .accounts({
-          depositorClawcash: depositorClawcashAccount,
+          depositorClink: depositorClinkAccount,
           depositor: authority.publicKey,
         })
         .rpc();
@@ -439,10 +765,12 @@ describe("Claw Cash Protocol v2", () => {
         .deposit(
           Array.from(note.commitment) as any,
           1,
-          0
+          0,
+          null,
+          null
         )
         .accounts({
-          depositorClawcash: depositorClawcashAccount,
+          depositorClink: depositorClinkAccount,
           depositor: authority.publicKey,
         })
         .rpc();
@@ -466,10 +794,12 @@ describe("Claw Cash Protocol v2", () => {
           .deposit(
             Array.from(note.commitment) as any,
             0,
-            i
+            i,
+            null,
+            null
           )
           .accounts({
-            depositorClawcash: depositorClawcashAccount,
+            depositorClink: depositorClinkAccount,
             depositor: authority.publicKey,
           })
           .rpc();
@@ -485,175 +815,2684 @@ describe("Claw Cash Protocol v2", () => {
         expectedRoot
       );
     });
-  });
 
-  // ─── Withdraw ─────────────────────────────────────────────────────
+    it("rejects depositing the same commitment twice", async () => {
+      for (const deposit of [
+        program.methods
+          .deposit(Array.from(pool0Notes[0].commitment) as any, 0, 3, null, null)
+          .accounts({
+            depositorClink: depositorClinkAccount,
+            depositor: authority.publicKey,
+          }),
+        program.methods
+          .queueDeposit(Array.from(pool0Notes[0].commitment) as any, 0, null, null)
+          .accounts({
+            depositorClink: depositorClinkAccount,
+            depositor: authority.publicKey,
+          }),
+        // Nullifiers are shared by all pools, so another pool refuses it too
+        program.methods
+          .queueDeposit(Array.from(pool0Notes[0].commitment) as any, 1, null, null)
+          .accounts({
+            depositorClink: depositorClinkAccount,
+            depositor: authority.publicKey,
+          }),
+      ]) {
+        try {
+          await deposit.rpc();
+          expect.fail("Should have rejected duplicate commitment");
+        } catch (err: any) {
+          expect(err.toString()).to.include("DuplicateCommitment");
+        }
+      }
 
-  describe("withdraw", () => {
-    it("withdraws from pool 0 with correct secret + Merkle proof", async () => {
-      const note = pool0Notes[0]; // First deposit
-      const leafIndex = 0;
-      const poolId = 0;
+      const [poolPDA] = getPoolPDA(programId, 0);
+      const pool = await program.account.pool.fetch(poolPDA);
+      expect(pool.nextIndex).to.equal(3);
+    });
 
-      // Compute Merkle proof
-      const proof = computeMerkleProof(pool0Leaves, leafIndex);
+    it("delivers an encrypted note to the payee's encryption key", async () => {
+      const payee = new ClawLinkCrypto(Keypair.generate());
+      const note = generateNote();
+      pool1Notes.push(note);
+      pool1Leaves.push(note.commitment);
 
-      // Use a fresh recipient for privacy
-      const recipient = Keypair.generate();
+      const encrypted = encryptNote(payee.getEncryptionPublicKey(), {
+        secret: note.secret,
+        nullifierPreimage: note.nullifierPreimage,
+      });
+      const signature = await program.methods
+        .deposit(Array.from(note.commitment) as any, 1, pool1Leaves.length - 1, {
+          ephemeralKey: Array.from(encrypted.ephemeralKey),
+          nonce: Array.from(encrypted.nonce),
+          ciphertext: Buffer.from(encrypted.ciphertext),
+        } as any,
+          null
+        )
+        .accounts({
+          depositorClink: depositorClinkAccount,
+          depositor: authority.publicKey,
+        })
+        .rpc({ commitment: "confirmed" });
+
+      const [event] = await cpiEvents(signature);
+      const carried = event.data.encryptedNote;
+      const received = payee.decryptNote({
+        ephemeralKey: Uint8Array.from(carried.ephemeralKey),
+        nonce: Uint8Array.from(carried.nonce),
+        ciphertext: Uint8Array.from(carried.ciphertext),
+      });
+      expect(Buffer.from(received!.secret)).to.deep.equal(note.secret);
+      expect(Buffer.from(received!.nullifierPreimage)).to.deep.equal(
+        note.nullifierPreimage
+      );
 
-      const [poolPDA] = getPoolPDA(programId, poolId);
-      const [vaultPDA] = getVaultPDA(programId, poolId);
-      const [nullifierPDA] = getNullifierPDA(programId, note.nullifierHash);
+      // Anyone else scanning the event gets nothing
+      const other = new ClawLinkCrypto(Keypair.generate());
+      expect(
+        other.decryptNote({
+          ephemeralKey: Uint8Array.from(carried.ephemeralKey),
+          nonce: Uint8Array.from(carried.nonce),
+          ciphertext: Uint8Array.from(carried.ciphertext),
+        })
+      ).to.be.null;
+    });
 
-      const recipientBefore = await provider.connection.getBalance(
-        recipient.publicKey
+    it("carries an audit memo only the viewing key opens", async () => {
+      const viewing = generateViewingKey();
+      const note = generateNote();
+      pool1Notes.push(note);
+      pool1Leaves.push(note.commitment);
+
+      const memo = Buffer.from("invoice #42");
+      const sealed = sealAuditMemo(viewing.publicKey, {
+        poolId: 1,
+        secret: note.secret,
+        nullifierPreimage: note.nullifierPreimage,
+        memo,
+      });
+      const signature = await program.methods
+        .deposit(Array.from(note.commitment) as any, 1, pool1Leaves.length - 1, null, {
+          ephemeralKey: Array.from(sealed.ephemeralKey),
+          nonce: Array.from(sealed.nonce),
+          ciphertext: Buffer.from(sealed.ciphertext),
+        } as any)
+        .accounts({
+          depositorClink: depositorClinkAccount,
+          depositor: authority.publicKey,
+        })
+        .rpc({ commitment: "confirmed" });
+
+      const [event] = await cpiEvents(signature);
+      expect(event.data.encryptedNote).to.be.null;
+      const carried = {
+        ephemeralKey: Uint8Array.from(event.data.auditMemo.ephemeralKey),
+        nonce: Uint8Array.from(event.data.auditMemo.nonce),
+        ciphertext: Uint8Array.from(event.data.auditMemo.ciphertext),
+      };
+      const opening = openAuditMemo(viewing.secretKey, carried)!;
+      expect(opening.poolId).to.equal(1);
+      expect(Buffer.from(opening.memo!)).to.deep.equal(memo);
+      expect(openAuditMemo(generateViewingKey().secretKey, carried)).to.be.null;
+
+      // The auditor recomputes the commitment rather than trusting the memo
+      const commitment = sha256(
+        Buffer.concat([Buffer.from(opening.secret), Buffer.from(opening.nullifierPreimage)])
+      );
+      expect(commitment).to.deep.equal(Buffer.from(event.data.commitment as number[]));
+      expect(sha256(Buffer.from(opening.nullifierPreimage))).to.deep.equal(
+        note.nullifierHash
       );
+    });
+
+    it("keeps a queued deposit's note and memo for its flush event", async () => {
+      const payee = new ClawLinkCrypto(Keypair.generate());
+      const viewing = generateViewingKey();
+      const note = generateNote();
+      pool1Notes.push(note);
+      pool1Leaves.push(note.commitment);
 
+      const encrypted = encryptNote(payee.getEncryptionPublicKey(), {
+        secret: note.secret,
+        nullifierPreimage: note.nullifierPreimage,
+      });
+      const memo = Buffer.from("invoice #43");
+      const sealed = sealAuditMemo(viewing.publicKey, {
+        poolId: 1,
+        secret: note.secret,
+        nullifierPreimage: note.nullifierPreimage,
+        memo,
+      });
+      const payload = (p: typeof sealed) =>
+        ({
+          ephemeralKey: Array.from(p.ephemeralKey),
+          nonce: Array.from(p.nonce),
+          ciphertext: Buffer.from(p.ciphertext),
+        }) as any;
       await program.methods
-        .withdraw(
-          Array.from(note.secret) as any,
-          Array.from(note.nullifierPreimage) as any,
-          Array.from(note.nullifierHash) as any,
-          leafIndex,
-          proof.map((p) => Array.from(p) as any)
-        )
+        .queueDeposit(Array.from(note.commitment) as any, 1, payload(encrypted), payload(sealed))
         .accounts({
-          pool: poolPDA,
-          recipient: recipient.publicKey,
-          payer: authority.publicKey,
+          depositorClink: depositorClinkAccount,
+          depositor: authority.publicKey,
         })
         .rpc();
+      const signature = await flushDeposits(1);
+
+      const [event] = await cpiEvents(signature);
+      expect(event.data.leafIndex).to.equal(pool1Leaves.length - 1);
+      const envelope = (carried: any) => ({
+        ephemeralKey: Uint8Array.from(carried.ephemeralKey),
+        nonce: Uint8Array.from(carried.nonce),
+        ciphertext: Uint8Array.from(carried.ciphertext),
+      });
+      const received = payee.decryptNote(envelope(event.data.encryptedNote));
+      expect(Buffer.from(received!.secret)).to.deep.equal(note.secret);
+      const opening = openAuditMemo(viewing.secretKey, envelope(event.data.auditMemo))!;
+      expect(Buffer.from(opening.secret)).to.deep.equal(note.secret);
+      expect(Buffer.from(opening.memo!)).to.deep.equal(memo);
+    });
+  });
 
-      // Verify recipient received SOL
-      const recipientAfter = await provider.connection.getBalance(
-        recipient.publicKey
-      );
-      expect(recipientAfter - recipientBefore).to.equal(
-        POOL_DENOMINATIONS[poolId]
-      );
+  // ─── Registered Pools ─────────────────────────────────────────────
 
-      // Verify nullifier account created
-      const nullifier = await program.account.nullifierAccount.fetch(
-        nullifierPDA
-      );
-      expect(Buffer.from(nullifier.nullifier as number[])).to.deep.equal(
-        note.nullifierHash
+  describe("register_pool", () => {
+    const poolId = 3;
+    const denomination = 0.01 * LAMPORTS_PER_SOL;
+    const depth = 10;
+    const leaves: Buffer[] = [];
+
+    it("registers and initializes a 0.01 SOL pool with depth 10", async () => {
+      await createPool(poolId, denomination, depth);
+
+      const [poolPDA] = getPoolPDA(programId, poolId);
+      const pool = await program.account.pool.fetch(poolPDA);
+      expect(pool.denomination.toNumber()).to.equal(denomination);
+      expect(pool.treeDepth).to.equal(depth);
+      expect(pool.filledSubtrees.length).to.equal(depth);
+      expect(Buffer.from(pool.currentRoot as number[])).to.deep.equal(
+        computeIncrementalRoot([], depth)
       );
-      expect(nullifier.poolId).to.equal(poolId);
     });
 
-    it("rejects withdrawal with wrong secret", async () => {
-      const note = pool0Notes[1]; // Second deposit (not yet withdrawn)
-      const leafIndex = 1;
-      const poolId = 0;
+    it("deposits and withdraws using the registered depth", async () => {
+      const note = generateNote();
+      leaves.push(note.commitment);
+      await depositNote(note, poolId, 0);
 
-      // Generate a wrong secret
-      const wrongSecret = Buffer.from(
-        Keypair.generate().secretKey.slice(0, 32)
+      const [poolPDA] = getPoolPDA(programId, poolId);
+      const pool = await program.account.pool.fetch(poolPDA);
+      expect(Buffer.from(pool.currentRoot as number[])).to.deep.equal(
+        computeIncrementalRoot(leaves, depth)
       );
 
-      const proof = computeMerkleProof(pool0Leaves, leafIndex);
-      const recipient = Keypair.generate();
-      const [poolPDA] = getPoolPDA(programId, poolId);
+      const recipient = await withdrawNote(note, poolId, leaves, 0, depth);
+      expect(await provider.connection.getBalance(recipient)).to.equal(denomination);
+    });
+
+    it("queues concurrent deposits and flushes them into the tree", async () => {
+      const notes = [generateNote(), generateNote()];
+      await Promise.all(
+        notes.map((note) =>
+          program.methods
+            .queueDeposit(Array.from(note.commitment) as any, poolId, null, null)
+            .accounts({
+              depositorClink: depositorClinkAccount,
+              depositor: authority.publicKey,
+            })
+            .rpc()
+        )
+      );
+
+      const [queuePDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("deposit_queue"), Buffer.from([poolId])],
+        programId
+      );
+      const queue = await program.account.depositQueue.fetch(queuePDA);
+      expect(queue.pending.length).to.equal(2);
+      const queued = (queue.pending as any[]).map((q) => Buffer.from(q.commitment));
 
+      // The pool stores leaf accounts, so the flush must be given them
       try {
         await program.methods
-          .withdraw(
-            Array.from(wrongSecret) as any,
-            Array.from(note.nullifierPreimage) as any,
-            Array.from(note.nullifierHash) as any,
-            leafIndex,
-            proof.map((p) => Array.from(p) as any)
-          )
-          .accounts({
-            pool: poolPDA,
-            recipient: recipient.publicKey,
-            payer: authority.publicKey,
-          })
+          .flushDeposits(poolId, 8)
+          .accounts({ payer: authority.publicKey })
           .rpc();
-        expect.fail("Should have rejected wrong secret");
+        expect.fail("Should have required the leaf accounts");
       } catch (err: any) {
-        // Should fail with InvalidProof because wrong secret → wrong commitment → wrong root
-        expect(err.toString()).to.include("InvalidProof");
+        expect(err.toString()).to.include("LeafAccountMismatch");
       }
-    });
 
-    it("rejects double-spend (same nullifier)", async () => {
-      const note = pool0Notes[0]; // Already withdrawn above
-      const leafIndex = 0;
-      const poolId = 0;
+      const flushSig = await flushDeposits(poolId);
 
-      const proof = computeMerkleProof(pool0Leaves, leafIndex);
-      const recipient = Keypair.generate();
+      leaves.push(...queued);
       const [poolPDA] = getPoolPDA(programId, poolId);
+      const pool = await program.account.pool.fetch(poolPDA);
+      expect(pool.nextIndex).to.equal(3);
+      expect(Buffer.from(pool.currentRoot as number[])).to.deep.equal(
+        computeIncrementalRoot(leaves, depth)
+      );
 
-      try {
-        await program.methods
-          .withdraw(
-            Array.from(note.secret) as any,
-            Array.from(note.nullifierPreimage) as any,
-            Array.from(note.nullifierHash) as any,
-            leafIndex,
-            proof.map((p) => Array.from(p) as any)
-          )
-          .accounts({
-            pool: poolPDA,
-            recipient: recipient.publicKey,
-            payer: authority.publicKey,
-          })
-          .rpc();
-        expect.fail("Should have rejected double-spend");
-      } catch (err: any) {
-        // The nullifier PDA already exists, so account init will fail
-        // This manifests as an "already in use" error from the runtime
-        expect(err.toString()).to.satisfy(
-          (s: string) =>
-            s.includes("already in use") ||
-            s.includes("NullifierAlreadyUsed") ||
-            s.includes("already been processed") ||
-            s.includes("0x0") ||
-            s.includes("custom program error") ||
-            s.includes("failed to send transaction"),
-          `Unexpected error: ${err.toString()}`
+      // Each flushed deposit got its leaf account, with rent from the cranker
+      for (const [i, commitment] of queued.entries()) {
+        const leaf = await program.account.commitmentLeaf.fetch(
+          getLeafPDA(programId, poolId, 1 + i)[0]
         );
+        expect(Buffer.from(leaf.commitment as number[])).to.deep.equal(commitment);
+        expect(leaf.leafIndex).to.equal(1 + i);
+        expect(leaf.payer.toBase58()).to.equal(authority.publicKey.toBase58());
       }
-    });
 
-    it("withdraws second deposit from pool 0 (leaf index 1)", async () => {
-      const note = pool0Notes[1]; // Second deposit
-      const leafIndex = 1;
-      const poolId = 0;
+      // Each flushed leaf is announced with its index and the recorded root
+      const events = await cpiEvents(flushSig);
+      expect(events.map((e) => e.name)).to.deep.equal([
+        "depositEvent",
+        "depositEvent",
+      ]);
+      events.forEach((e, i) => {
+        expect(Buffer.from(e.data.commitment as number[])).to.deep.equal(
+          queued[i]
+        );
+        expect(e.data.leafIndex).to.equal(1 + i);
+        expect(e.data.poolId).to.equal(poolId);
+        expect(Buffer.from(e.data.root as number[])).to.deep.equal(
+          computeIncrementalRoot(leaves, depth)
+        );
+      });
+      expect(
+        (await program.account.depositQueue.fetch(queuePDA)).pending.length
+      ).to.equal(0);
 
-      const proof = computeMerkleProof(pool0Leaves, leafIndex);
+      // A queued note withdraws like any other once flushed
+      const note = notes.find((n) => n.commitment.equals(queued[0]))!;
       const recipient = Keypair.generate();
-      const [poolPDA] = getPoolPDA(programId, poolId);
-
-      const recipientBefore = await provider.connection.getBalance(
+      const withdrawalCommit = await commitWithdrawal(
+        note.secret,
+        note.nullifierPreimage,
         recipient.publicKey
       );
-
-      await program.methods
+      const withdrawSig = await program.methods
         .withdraw(
           Array.from(note.secret) as any,
           Array.from(note.nullifierPreimage) as any,
           Array.from(note.nullifierHash) as any,
-          leafIndex,
-          proof.map((p) => Array.from(p) as any)
+          0,
+          1,
+          computeMerkleProof(leaves, 1, depth).map((p) => Array.from(p) as any),
+          new BN(0)
         )
         .accounts({
+          nullifierShard: getNullifierShardPDA(programId, note.nullifierHash)[0],
           pool: poolPDA,
+          withdrawalCommit,
           recipient: recipient.publicKey,
+          relayer: authority.publicKey,
           payer: authority.publicKey,
         })
+        .rpc({ commitment: "confirmed" });
+      expect(
+        await provider.connection.getBalance(recipient.publicKey)
+      ).to.equal(denomination);
+
+      const [withdrawEvent] = await cpiEvents(withdrawSig);
+      expect(withdrawEvent.name).to.equal("withdrawEvent");
+      expect(
+        Buffer.from(withdrawEvent.data.nullifierHash as number[])
+      ).to.deep.equal(note.nullifierHash);
+      expect(withdrawEvent.data.recipient.toBase58()).to.equal(
+        recipient.publicKey.toBase58()
+      );
+      expect(withdrawEvent.data.fee.toNumber()).to.equal(0);
+    });
+
+    it("rejects flushing an empty queue", async () => {
+      try {
+        await flushDeposits(poolId);
+        expect.fail("Should have rejected empty flush");
+      } catch (err: any) {
+        expect(err.toString()).to.include("DepositQueueEmpty");
+      }
+    });
+
+    it("keeps leaf accounts while the pool stores them", async () => {
+      try {
+        await program.methods
+          .closeLeaf(poolId, 0, 0)
+          .accounts({ payer: authority.publicKey })
+          .rpc();
+        expect.fail("Should have kept the leaf account");
+      } catch (err: any) {
+        expect(err.toString()).to.include("LeafStillRequired");
+      }
+    });
+
+    it("switches to events-only deposits and refunds old leaf rent", async () => {
+      await program.methods
+        .updateLeafStorage(poolId, false)
+        .accounts({ authority: authority.publicKey })
         .rpc();
 
-      const recipientAfter = await provider.connection.getBalance(
-        recipient.publicKey
-      );
-      expect(recipientAfter - recipientBefore).to.equal(
-        POOL_DENOMINATIONS[poolId]
+      const [leafPDA] = getLeafPDA(programId, poolId, 0);
+      const rent = await provider.connection.getBalance(leafPDA);
+      expect(rent).to.be.greaterThan(0);
+      await program.methods
+        .closeLeaf(poolId, 0, 0)
+        .accounts({ payer: authority.publicKey })
+        .rpc();
+      expect(await provider.connection.getAccountInfo(leafPDA)).to.be.null;
+
+      // New deposits create no leaf account; the event carries the leaf
+      const note = generateNote();
+      leaves.push(note.commitment);
+      const sig = await program.methods
+        .deposit(Array.from(note.commitment) as any, poolId, 3, null, null)
+        .accountsPartial({
+          depositorClink: depositorClinkAccount,
+          depositor: authority.publicKey,
+          commitmentLeaf: null,
+        })
+        .rpc({ commitment: "confirmed" });
+
+      const [event] = await cpiEvents(sig);
+      expect(event.name).to.equal("depositEvent");
+      expect(event.data.leafIndex).to.equal(3);
+      expect(Buffer.from(event.data.root as number[])).to.deep.equal(
+        computeIncrementalRoot(leaves, depth)
       );
     });
+
+    it("rejects registering an existing pool ID", async () => {
+      try {
+        await program.methods
+          .registerPool(0, new BN(denomination), depth, null)
+          .accounts({ authority: authority.publicKey })
+          .rpc();
+        expect.fail("Should have rejected duplicate pool ID");
+      } catch (err: any) {
+        expect(err.toString()).to.include("PoolAlreadyRegistered");
+      }
+    });
+
+    it("rejects deposits into unregistered pools", async () => {
+      try {
+        await program.methods
+          .deposit(Array.from(generateNote().commitment) as any, 9, 0, null, null)
+          .accounts({
+            depositorClink: depositorClinkAccount,
+            depositor: authority.publicKey,
+          })
+          .rpc();
+        expect.fail("Should have rejected unregistered pool");
+      } catch (err: any) {
+        expect(err.toString()).to.satisfy(
+          (s: string) =>
+            s.includes("InvalidPool") || s.includes("AccountNotInitialized"),
+          `Unexpected error: ${err.toString()}`
+        );
+      }
+    });
+  });
+
+  // ─── Pre-upgrade Pools ────────────────────────────────────────────
+
+  describe("migrate_pool", () => {
+    // Created before the root history and registry: the pool and its vault
+    // are preloaded with two deposits, neither withdrawn
+    const legacy = legacyVectors.pool;
+    const poolId = legacy.poolId;
+    const leaves = legacy.leaves.map((l) => Buffer.from(l, "hex"));
+
+    it("rejects migrating an unregistered pool", async () => {
+      try {
+        await program.methods
+          .migratePool(poolId)
+          .accounts({ authority: authority.publicKey })
+          .rpc();
+        expect.fail("Should have required a registry entry");
+      } catch (err: any) {
+        expect(err.toString()).to.include("InvalidPool");
+      }
+    });
+
+    it("upgrades the pool and keeps its legacy tree", async () => {
+      await program.methods
+        .registerPool(poolId, new BN(legacy.denomination), legacy.treeDepth, null)
+        .accounts({ authority: authority.publicKey })
+        .rpc();
+      await program.methods
+        .migratePool(poolId)
+        .accounts({ authority: authority.publicKey })
+        .rpc();
+
+      const [poolPDA] = getPoolPDA(programId, poolId);
+      const pool = await program.account.pool.fetch(poolPDA);
+      expect(pool.treeVersion).to.deep.equal({ legacy: {} });
+      expect(pool.treeDepth).to.equal(legacy.treeDepth);
+      expect(pool.nextIndex).to.equal(leaves.length);
+      expect(pool.totalDeposits.toNumber()).to.equal(leaves.length);
+      expect(pool.totalWithdrawals.toNumber()).to.equal(0);
+      expect(pool.storeLeafAccounts).to.equal(true);
+      expect(Buffer.from(pool.currentRoot as number[])).to.deep.equal(
+        Buffer.from(legacy.root, "hex")
+      );
+      // Legacy vaults held only their notes; migrating adds the rent
+      expect(
+        await provider.connection.getBalance(getVaultPDA(programId, poolId)[0])
+      ).to.equal(
+        leaves.length * legacy.denomination +
+          (await provider.connection.getMinimumBalanceForRentExemption(0))
+      );
+      expect(
+        await program.methods
+          .isKnownRoot(hexBytes(legacy.root) as any)
+          .accounts({ pool: poolPDA })
+          .view()
+      ).to.equal(true);
+    });
+
+    it("rejects migrating a pool twice", async () => {
+      try {
+        await program.methods
+          .migratePool(poolId)
+          .accounts({ authority: authority.publicKey })
+          .rpc();
+        expect.fail("Should have rejected an upgraded pool");
+      } catch (err: any) {
+        // The queue and window already exist, so creating them fails first
+        expect(err.toString()).to.satisfy(
+          (s: string) => s.includes("NotLegacyAccount") || s.includes("already in use"),
+          `Unexpected error: ${err.toString()}`
+        );
+      }
+    });
+
+    it("withdraws a note deposited before the upgrade", async () => {
+      const note = legacy.note;
+      const recipient = Keypair.generate();
+      const withdrawalCommit = await commitWithdrawal(
+        Buffer.from(note.secret, "hex"),
+        Buffer.from(note.nullifierPreimage, "hex"),
+        recipient.publicKey
+      );
+      await program.methods
+        .withdraw(
+          hexBytes(note.secret) as any,
+          hexBytes(note.nullifierPreimage) as any,
+          hexBytes(note.nullifierHash) as any,
+          0,
+          note.leafIndex,
+          legacy.proof.map((p) => hexBytes(p) as any),
+          new BN(0)
+        )
+        .accounts({
+          nullifierShard: getNullifierShardPDA(
+            programId,
+            Buffer.from(note.nullifierHash, "hex")
+          )[0],
+          pool: getPoolPDA(programId, poolId)[0],
+          recipient: recipient.publicKey,
+          relayer: authority.publicKey,
+          withdrawalCommit,
+          payer: authority.publicKey,
+        })
+        .rpc();
+
+      expect(
+        await provider.connection.getBalance(recipient.publicKey)
+      ).to.equal(legacy.denomination);
+      const pool = await program.account.pool.fetch(getPoolPDA(programId, poolId)[0]);
+      expect(pool.withdrawalCount).to.equal(1);
+      expect(pool.totalWithdrawals.toNumber()).to.equal(1);
+    });
+
+    it("queues and flushes deposits with the accounts migration created", async () => {
+      const note = generateNote();
+      await program.methods
+        .queueDeposit(Array.from(note.commitment) as any, poolId, null, null)
+        .accounts({
+          depositorClink: depositorClinkAccount,
+          depositor: authority.publicKey,
+        })
+        .rpc();
+      const sig = await flushDeposits(poolId);
+
+      const [poolPDA] = getPoolPDA(programId, poolId);
+      const pool = await program.account.pool.fetch(poolPDA);
+      expect(pool.nextIndex).to.equal(leaves.length + 1);
+      const [event] = await cpiEvents(sig);
+      expect(event.data.leafIndex).to.equal(leaves.length);
+      expect(Buffer.from(event.data.root as number[])).to.deep.equal(
+        Buffer.from(pool.currentRoot as number[])
+      );
+      expect(
+        await program.methods
+          .isKnownRoot(event.data.root as any)
+          .accounts({ pool: poolPDA })
+          .view()
+      ).to.equal(true);
+    });
+
+    it("reclaims the rent of leaf accounts from before the upgrade for the protocol", async () => {
+      const { leafIndex } = legacy.leafAccount;
+      const leafPDA = new PublicKey(legacy.leafAccount.address);
+      try {
+        await program.methods
+          .closeLegacyLeaf(poolId, leafIndex)
+          .accounts({ authority: authority.publicKey })
+          .rpc();
+        expect.fail("Should have kept the leaf account");
+      } catch (err: any) {
+        expect(err.toString()).to.include("LeafStillRequired");
+      }
+
+      await program.methods
+        .updateLeafStorage(poolId, false)
+        .accounts({ authority: authority.publicKey })
+        .rpc();
+      const rent = await provider.connection.getBalance(leafPDA);
+      const before = await provider.connection.getBalance(authority.publicKey);
+      await program.methods
+        .closeLegacyLeaf(poolId, leafIndex)
+        .accounts({ authority: authority.publicKey })
+        .rpc({ commitment: "confirmed" });
+
+      expect(await provider.connection.getAccountInfo(leafPDA)).to.be.null;
+      // Less the transaction fee the authority paid
+      expect(
+        await provider.connection.getBalance(authority.publicKey, "confirmed")
+      ).to.be.greaterThan(before + rent - 10_000);
+    });
+  });
+
+  // ─── SPL Token Pools ──────────────────────────────────────────────
+
+  describe("token pool", () => {
+    const poolId = 4;
+    const denomination = 50_000_000; // 50 tokens (6 decimals)
+    const depth = 10;
+    const leaves: Buffer[] = [];
+    let tokenMint: PublicKey;
+    let depositorToken: PublicKey;
+    const [tokenVault] = PublicKey.findProgramAddressSync(
+      [Buffer.from("token_vault"), Buffer.from([poolId])],
+      programId
+    );
+
+    before(async () => {
+      tokenMint = await createMint(
+        provider.connection,
+        authority.payer,
+        authority.publicKey,
+        null,
+        6
+      );
+      depositorToken = (
+        await getOrCreateAssociatedTokenAccount(
+          provider.connection,
+          authority.payer,
+          tokenMint,
+          authority.publicKey
+        )
+      ).address;
+      await mintTo(
+        provider.connection,
+        authority.payer,
+        tokenMint,
+        depositorToken,
+        authority.publicKey,
+        1_000_000_000
+      );
+    });
+
+    it("registers a token pool and creates its token vault", async () => {
+      await createPool(poolId, denomination, depth, "sha256", tokenMint);
+      await program.methods
+        .initializeTokenVault(poolId)
+        .accounts({ mint: tokenMint, authority: authority.publicKey })
+        .rpc();
+
+      const [poolPDA] = getPoolPDA(programId, poolId);
+      const pool = await program.account.pool.fetch(poolPDA);
+      expect(pool.mint.toBase58()).to.equal(tokenMint.toBase58());
+      const vault = await getAccount(provider.connection, tokenVault);
+      expect(vault.mint.toBase58()).to.equal(tokenMint.toBase58());
+    });
+
+    it("deposits and withdraws tokens through the shared tree", async () => {
+      const note = generateNote();
+      leaves.push(note.commitment);
+      await program.methods
+        .deposit(Array.from(note.commitment) as any, poolId, 0, null, null)
+        .accounts({
+          depositorClink: depositorClinkAccount,
+          depositor: authority.publicKey,
+          tokenVault,
+          depositorToken,
+        })
+        .rpc();
+      expect(
+        Number((await getAccount(provider.connection, tokenVault)).amount)
+      ).to.equal(denomination);
+
+      const recipient = Keypair.generate();
+      const recipientToken = (
+        await getOrCreateAssociatedTokenAccount(
+          provider.connection,
+          authority.payer,
+          tokenMint,
+          recipient.publicKey
+        )
+      ).address;
+      const fee = denomination / 100;
+      const withdrawalCommit = await commitWithdrawal(
+        note.secret,
+        note.nullifierPreimage,
+        recipient.publicKey,
+        authority.publicKey,
+        fee
+      );
+      const [poolPDA] = getPoolPDA(programId, poolId);
+      await program.methods
+        .withdraw(
+          Array.from(note.secret) as any,
+          Array.from(note.nullifierPreimage) as any,
+          Array.from(note.nullifierHash) as any,
+          0,
+          0,
+          computeMerkleProof(leaves, 0, depth).map((p) => Array.from(p) as any),
+          new BN(fee)
+        )
+        .accounts({
+          nullifierShard: getNullifierShardPDA(programId, note.nullifierHash)[0],
+          pool: poolPDA,
+          withdrawalCommit,
+          recipient: recipient.publicKey,
+          relayer: authority.publicKey,
+          payer: authority.publicKey,
+          tokenVault,
+          recipientToken,
+          relayerToken: depositorToken,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .rpc();
+
+      expect(
+        Number((await getAccount(provider.connection, recipientToken)).amount)
+      ).to.equal(denomination - fee);
+      expect(
+        Number((await getAccount(provider.connection, tokenVault)).amount)
+      ).to.equal(0);
+    });
+
+    it("rejects token deposits without token accounts", async () => {
+      try {
+        await program.methods
+          .deposit(Array.from(generateNote().commitment) as any, poolId, 1, null, null)
+          .accounts({
+            depositorClink: depositorClinkAccount,
+            depositor: authority.publicKey,
+          })
+          .rpc();
+        expect.fail("Should have required token accounts");
+      } catch (err: any) {
+        expect(err.toString()).to.include("MissingTokenAccounts");
+      }
+    });
+
+    it("closes the drained pool's token vault along with it", async () => {
+      const rent = await provider.connection.getBalance(tokenVault);
+      const before = await provider.connection.getBalance(authority.publicKey);
+      await program.methods
+        .closePool(poolId)
+        .accounts({
+          authority: authority.publicKey,
+          tokenVault,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .rpc({ commitment: "confirmed" });
+
+      expect(await provider.connection.getAccountInfo(tokenVault)).to.be.null;
+      expect(
+        await provider.connection.getAccountInfo(getPoolPDA(programId, poolId)[0])
+      ).to.be.null;
+      // The token vault's rent went to the authority, on top of the pool's
+      expect(
+        await provider.connection.getBalance(authority.publicKey, "confirmed")
+      ).to.be.greaterThan(before + rent);
+    });
+  });
+
+  // ─── Tree Rollover ────────────────────────────────────────────────
+
+  describe("rollover_tree", () => {
+    const poolId = 5;
+    const denomination = 0.01 * LAMPORTS_PER_SOL;
+    const depth = 1; // two leaves per tree
+    const tree0: Note[] = [generateNote(), generateNote()];
+    const tree1: Note[] = [generateNote()];
+    const [poolPDA] = getPoolPDA(programId, poolId);
+    const [archivePDA] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("tree"),
+        Buffer.from([poolId]),
+        new BN(0).toArrayLike(Buffer, "le", 4),
+      ],
+      programId
+    );
+
+    it("fills a tree and rejects further deposits", async () => {
+      await createPool(poolId, denomination, depth);
+      await depositNote(tree0[0], poolId, 0);
+      await depositNote(tree0[1], poolId, 1);
+
+      try {
+        await depositNote(generateNote(), poolId, 2);
+        expect.fail("Should have rejected a deposit into a full tree");
+      } catch (err: any) {
+        expect(err.toString()).to.include("MerkleTreeFull");
+      }
+    });
+
+    it("archives the full tree and starts a new one", async () => {
+      await program.methods
+        .rolloverTree(poolId)
+        .accounts({ payer: authority.publicKey })
+        .rpc();
+
+      const archive = await program.account.treeArchive.fetch(archivePDA);
+      expect(archive.treeIndex).to.equal(0);
+      expect(Buffer.from(archive.root as number[])).to.deep.equal(
+        computeIncrementalRoot(
+          tree0.map((n) => n.commitment),
+          depth
+        )
+      );
+
+      const pool = await program.account.pool.fetch(poolPDA);
+      expect(pool.treeIndex).to.equal(1);
+      expect(pool.nextIndex).to.equal(0);
+
+      await depositNote(tree1[0], poolId, 0, 1);
+      try {
+        await program.methods
+          .rolloverTree(poolId)
+          .accounts({ payer: authority.publicKey })
+          .rpc();
+        expect.fail("Should have rejected rolling over a non-full tree");
+      } catch (err: any) {
+        expect(err.toString()).to.include("TreeNotFull");
+      }
+    });
+
+    it("withdraws from both the archived and the live tree", async () => {
+      const commitments = (notes: Note[]) => notes.map((n) => n.commitment);
+      const fromArchive = await withdrawNote(
+        tree0[1],
+        poolId,
+        commitments(tree0),
+        1,
+        depth,
+        0
+      );
+      const fromLive = await withdrawNote(
+        tree1[0],
+        poolId,
+        commitments(tree1),
+        0,
+        depth,
+        1
+      );
+      expect(await provider.connection.getBalance(fromArchive)).to.equal(
+        denomination
+      );
+      expect(await provider.connection.getBalance(fromLive)).to.equal(
+        denomination
+      );
+
+      const pool = await program.account.pool.fetch(poolPDA);
+      expect(pool.withdrawalCount).to.equal(2);
+    });
+  });
+
+  // ─── Withdrawal Delay ─────────────────────────────────────────────
+
+  describe("update_withdrawal_delay", () => {
+    const poolId = 8;
+    const denomination = 0.01 * LAMPORTS_PER_SOL;
+    const depth = 5;
+    const notes: Note[] = [generateNote(), generateNote()];
+    const leaves = notes.map((n) => n.commitment);
+    const [poolPDA] = getPoolPDA(programId, poolId);
+
+    /** Withdraw `notes[leafIndex]`, proving against the root over `treeLeaves` */
+    const withdrawAgainst = (leafIndex: number, treeLeaves: Buffer[]) =>
+      withdrawNote(notes[leafIndex], poolId, treeLeaves, leafIndex, depth);
+
+    before(async () => {
+      await createPool(poolId, denomination, depth);
+      await program.methods
+        .updateWithdrawalDelay(poolId, 1, 0)
+        .accounts({ authority: authority.publicKey })
+        .rpc();
+      await depositNote(notes[0], poolId, 0);
+    });
+
+    it("rejects withdrawing before enough later deposits", async () => {
+      try {
+        await withdrawAgainst(0, leaves.slice(0, 1));
+        expect.fail("Should have required a later deposit");
+      } catch (err: any) {
+        expect(err.toString()).to.include("AnonymitySetTooSmall");
+      }
+    });
+
+    it("accepts an older root once enough deposits follow it", async () => {
+      await depositNote(notes[1], poolId, 1);
+
+      // The latest root has no deposits after it yet
+      try {
+        await withdrawAgainst(0, leaves);
+        expect.fail("Should have rejected the latest root");
+      } catch (err: any) {
+        expect(err.toString()).to.include("AnonymitySetTooSmall");
+      }
+      await withdrawAgainst(0, leaves.slice(0, 1));
+
+      const pool = await program.account.pool.fetch(poolPDA);
+      expect(pool.withdrawalCount).to.equal(1);
+    });
+
+    it("rejects roots younger than the minimum delay", async () => {
+      await program.methods
+        .updateWithdrawalDelay(poolId, 0, 3600)
+        .accounts({ authority: authority.publicKey })
+        .rpc();
+      try {
+        await withdrawAgainst(1, leaves);
+        expect.fail("Should have enforced the delay");
+      } catch (err: any) {
+        expect(err.toString()).to.include("WithdrawalTooEarly");
+      }
+    });
+
+    it("rejects anonymity sets the root history cannot cover", async () => {
+      try {
+        await program.methods
+          .updateWithdrawalDelay(poolId, 30, 0)
+          .accounts({ authority: authority.publicKey })
+          .rpc();
+        expect.fail("Should have rejected min_deposits_after of 30");
+      } catch (err: any) {
+        expect(err.toString()).to.include("InvalidWithdrawalDelay");
+      }
+    });
+
+    it("keeps an old enough root through a burst of deposits", async () => {
+      const delay = 60;
+      await program.methods
+        .updateWithdrawalDelay(poolId, 0, delay)
+        .accounts({ authority: authority.publicKey })
+        .rpc();
+      let pool = await program.account.pool.fetch(poolPDA);
+      const checkpoint = pool.delayCheckpoints[1];
+      expect(checkpoint.leafCount).to.equal(2);
+
+      // Fill the tree: 30 new roots push the root over `notes` out of the
+      // history, and none of them is old enough
+      await program.methods
+        .updateFee(new BN(0))
+        .accounts({ authority: authority.publicKey })
+        .rpc();
+      for (let i = 2; i < 32; i++) {
+        await depositNote(generateNote(), poolId, i);
+      }
+      await program.methods
+        .updateFee(new BN(FEE_AMOUNT))
+        .accounts({ authority: authority.publicKey })
+        .rpc();
+      try {
+        await withdrawAgainst(1, leaves);
+        expect.fail("Should have enforced the delay on the checkpoint");
+      } catch (err: any) {
+        expect(err.toString()).to.include("WithdrawalTooEarly");
+      }
+
+      // The checkpoint outlives the history and is accepted once old enough
+      const readyAt = checkpoint.recordedAt.toNumber() + delay;
+      while (
+        (await provider.connection.getBlockTime(
+          await provider.connection.getSlot("confirmed")
+        ))! < readyAt
+      ) {
+        await new Promise((resolve) => setTimeout(resolve, 1000));
+      }
+      await withdrawAgainst(1, leaves);
+      pool = await program.account.pool.fetch(poolPDA);
+      expect(pool.withdrawalCount).to.equal(2);
+    });
+  });
+
+  // ─── Deposit Limits ───────────────────────────────────────────────
+
+  describe("update_deposit_limits", () => {
+    const poolId = 10;
+    const denomination = 0.01 * LAMPORTS_PER_SOL;
+    let leafIndex = 0;
+
+    const deposit = () =>
+      program.methods
+        .deposit(Array.from(generateNote().commitment) as any, poolId, leafIndex, null, null)
+        .accounts({
+          depositorClink: depositorClinkAccount,
+          depositor: authority.publicKey,
+        })
+        .rpc()
+        .then(() => leafIndex++);
+
+    const setLimits = (windowSeconds: number, maxDeposits: number, maxPerDepositor: number) =>
+      program.methods
+        .updateDepositLimits(poolId, windowSeconds, maxDeposits, maxPerDepositor)
+        .accounts({ authority: authority.publicKey })
+        .rpc();
+
+    const chainTime = async () =>
+      (await provider.connection.getBlockTime(
+        await provider.connection.getSlot("confirmed")
+      ))!;
+
+    before(async () => {
+      await createPool(poolId, denomination, 5);
+    });
+
+    it("rejects limits without a window", async () => {
+      try {
+        await setLimits(0, 2, 0);
+        expect.fail("Should have required a window");
+      } catch (err: any) {
+        expect(err.toString()).to.include("InvalidDepositLimits");
+      }
+    });
+
+    it("caps the pool's deposits per window", async () => {
+      await setLimits(3600, 2, 0);
+      await deposit();
+      await deposit();
+      try {
+        await deposit();
+        expect.fail("Should have hit the pool's limit");
+      } catch (err: any) {
+        expect(err.toString()).to.include("PoolDepositLimitReached");
+      }
+    });
+
+    it("caps each depositor's deposits per window", async () => {
+      await setLimits(3600, 5, 1);
+      await deposit();
+      try {
+        await deposit();
+        expect.fail("Should have hit the depositor's limit");
+      } catch (err: any) {
+        expect(err.toString()).to.include("DepositorLimitReached");
+      }
+
+      const [windowPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("deposit_window"), Buffer.from([poolId])],
+        programId
+      );
+      const window = await program.account.depositWindow.fetch(windowPDA);
+      expect(window.count).to.equal(1);
+      expect(window.depositors.length).to.equal(1);
+      expect(window.depositors[0].depositor.toBase58()).to.equal(
+        authority.publicKey.toBase58()
+      );
+      expect(window.depositors[0].count).to.equal(1);
+    });
+
+    it("allows limits beyond what a log of deposits could hold", async () => {
+      await setLimits(86400, 1000, 0);
+      await deposit();
+
+      const [windowPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("deposit_window"), Buffer.from([poolId])],
+        programId
+      );
+      const window = await program.account.depositWindow.fetch(windowPDA);
+      expect(window.maxDeposits).to.equal(1000);
+      expect(window.count).to.equal(1);
+      expect(window.depositors.length).to.equal(0);
+    });
+
+    it("starts counting again once the window ends", async () => {
+      const windowSeconds = 2;
+      await setLimits(windowSeconds, 1, 0);
+      await deposit();
+      const depositedAt = await chainTime();
+      try {
+        await deposit();
+        expect.fail("Should have hit the pool's limit");
+      } catch (err: any) {
+        expect(err.toString()).to.include("PoolDepositLimitReached");
+      }
+
+      // Wait on the validator's clock, which the window is measured in
+      while ((await chainTime()) <= depositedAt + windowSeconds) {
+        await new Promise((resolve) => setTimeout(resolve, 500));
+      }
+      await deposit();
+    });
+
+    it("lifts the limits", async () => {
+      await setLimits(0, 0, 0);
+      await deposit();
+      await deposit();
+    });
+  });
+
+  // ─── Poseidon Pools ───────────────────────────────────────────────
+
+  describe("poseidon pool", () => {
+    it("rejects commitments outside the BN254 scalar field", async () => {
+      try {
+        await program.methods
+          .deposit(Array.from(Buffer.alloc(32, 0xff)) as any, 2, 0, null, null)
+          .accounts({
+            depositorClink: depositorClinkAccount,
+            depositor: authority.publicKey,
+          })
+          .rpc();
+        expect.fail("Should have rejected non-field commitment");
+      } catch (err: any) {
+        expect(err.toString()).to.include("InvalidFieldElement");
+      }
+    });
+  });
+
+  // ─── Withdraw ─────────────────────────────────────────────────────
+
+  describe("withdraw", () => {
+    it("withdraws from pool 0 with correct secret + Merkle proof", async () => {
+      const note = pool0Notes[0]; // First deposit
+      const leafIndex = 0;
+      const poolId = 0;
+
+      // Compute Merkle proof
+      const proof = computeMerkleProof(pool0Leaves, leafIndex);
+
+      // Use a fresh recipient for privacy
+      const recipient = Keypair.generate();
+
+      const [poolPDA] = getPoolPDA(programId, poolId);
+      const [vaultPDA] = getVaultPDA(programId, poolId);
+
+      const recipientBefore = await provider.connection.getBalance(
+        recipient.publicKey
+      );
+
+      const withdrawalCommit = await commitWithdrawal(
+        note.secret,
+        note.nullifierPreimage,
+        recipient.publicKey
+      );
+
+      await program.methods
+        .withdraw(
+          Array.from(note.secret) as any,
+          Array.from(note.nullifierPreimage) as any,
+          Array.from(note.nullifierHash) as any,
+          0,
+          leafIndex,
+          proof.map((p) => Array.from(p) as any),
+          new BN(0)
+        )
+        .accounts({
+          nullifierShard: getNullifierShardPDA(programId, note.nullifierHash)[0],
+          pool: poolPDA,
+          recipient: recipient.publicKey,
+          relayer: authority.publicKey,
+          withdrawalCommit,
+          payer: authority.publicKey,
+        })
+        .rpc();
+
+      // Verify recipient received SOL
+      const recipientAfter = await provider.connection.getBalance(
+        recipient.publicKey
+      );
+      expect(recipientAfter - recipientBefore).to.equal(
+        POOL_DENOMINATIONS[poolId]
+      );
+
+      // Verify the nullifier landed in its shard, not in a per-nullifier PDA
+      expect(await isSpent(note.nullifierHash)).to.equal(true);
+      expect(
+        await provider.connection.getAccountInfo(
+          getNullifierPDA(programId, note.nullifierHash)[0]
+        )
+      ).to.equal(null);
+    });
+
+    it("rejects a copied withdrawal redirected to another recipient", async () => {
+      const note = pool1Notes[0];
+      const leafIndex = 0;
+      const poolId = 1;
+
+      const proof = computeMerkleProof(pool1Leaves, leafIndex);
+      const [poolPDA] = getPoolPDA(programId, poolId);
+      const honestRecipient = Keypair.generate();
+      const attackerRecipient = Keypair.generate();
+
+      const withdrawalCommit = await commitWithdrawal(
+        note.secret,
+        note.nullifierPreimage,
+        honestRecipient.publicKey
+      );
+
+      const withdrawIx = (recipient: PublicKey, commit: PublicKey) =>
+        program.methods
+          .withdraw(
+            Array.from(note.secret) as any,
+            Array.from(note.nullifierPreimage) as any,
+            Array.from(note.nullifierHash) as any,
+            0,
+            leafIndex,
+            proof.map((p) => Array.from(p) as any),
+            new BN(0)
+          )
+          .accounts({
+            nullifierShard: getNullifierShardPDA(programId, note.nullifierHash)[0],
+            pool: poolPDA,
+            withdrawalCommit: commit,
+            recipient,
+            relayer: authority.publicKey,
+            payer: authority.publicKey,
+          });
+
+      // Front-runner replays the honest reveal with their own recipient
+      try {
+        await withdrawIx(attackerRecipient.publicKey, withdrawalCommit).rpc();
+        expect.fail("Should have rejected swapped recipient");
+      } catch (err: any) {
+        expect(err.toString()).to.include("WithdrawalNotCommitted");
+      }
+
+      // Committing and revealing in the same transaction doesn't help either
+      const attackerBinding = withdrawalBinding(
+        note.secret,
+        note.nullifierPreimage,
+        attackerRecipient.publicKey,
+        authority.publicKey,
+        0
+      );
+      const [attackerCommit] = getWithdrawCommitPDA(programId, attackerBinding);
+      const tx = new Transaction()
+        .add(
+          await program.methods
+            .commitWithdrawal(Array.from(attackerBinding) as any)
+            .accounts({ payer: authority.publicKey })
+            .instruction()
+        )
+        .add(
+          await withdrawIx(attackerRecipient.publicKey, attackerCommit).instruction()
+        );
+      try {
+        await provider.sendAndConfirm(tx);
+        expect.fail("Should have rejected same-slot commit");
+      } catch (err: any) {
+        // A raw transaction's error carries the program logs, not a parsed code
+        const anchorError = anchor.AnchorError.parse(err.logs ?? []);
+        expect(anchorError?.error.errorCode.code, err.toString()).to.equal(
+          "CommitTooRecent"
+        );
+      }
+
+      // The honest withdrawal still goes through
+      await withdrawIx(honestRecipient.publicKey, withdrawalCommit).rpc();
+      const balance = await provider.connection.getBalance(
+        honestRecipient.publicKey
+      );
+      expect(balance).to.equal(POOL_DENOMINATIONS[poolId]);
+      expect(
+        await provider.connection.getBalance(attackerRecipient.publicKey)
+      ).to.equal(0);
+    });
+
+    it("rejects withdrawal with wrong secret", async () => {
+      const note = pool0Notes[1]; // Second deposit (not yet withdrawn)
+      const leafIndex = 1;
+      const poolId = 0;
+
+      // Generate a wrong secret
+      const wrongSecret = Buffer.from(
+        Keypair.generate().secretKey.slice(0, 32)
+      );
+
+      const proof = computeMerkleProof(pool0Leaves, leafIndex);
+      const recipient = Keypair.generate();
+      const [poolPDA] = getPoolPDA(programId, poolId);
+
+      const withdrawalCommit = await commitWithdrawal(
+        wrongSecret,
+        note.nullifierPreimage,
+        recipient.publicKey
+      );
+
+      try {
+        await program.methods
+          .withdraw(
+            Array.from(wrongSecret) as any,
+            Array.from(note.nullifierPreimage) as any,
+            Array.from(note.nullifierHash) as any,
+            0,
+            leafIndex,
+            proof.map((p) => Array.from(p) as any),
+            new BN(0)
+          )
+          .accounts({
+            nullifierShard: getNullifierShardPDA(programId, note.nullifierHash)[0],
+            pool: poolPDA,
+            recipient: recipient.publicKey,
+            relayer: authority.publicKey,
+            withdrawalCommit,
+            payer: authority.publicKey,
+          })
+          .rpc();
+        expect.fail("Should have rejected wrong secret");
+      } catch (err: any) {
+        // Should fail with InvalidProof because wrong secret → wrong commitment → wrong root
+        expect(err.toString()).to.include("InvalidProof");
+      }
+    });
+
+    it("rejects double-spend (same nullifier)", async () => {
+      const note = pool0Notes[0]; // Already withdrawn above
+      const leafIndex = 0;
+      const poolId = 0;
+
+      const proof = computeMerkleProof(pool0Leaves, leafIndex);
+      const recipient = Keypair.generate();
+      const [poolPDA] = getPoolPDA(programId, poolId);
+
+      const withdrawalCommit = await commitWithdrawal(
+        note.secret,
+        note.nullifierPreimage,
+        recipient.publicKey
+      );
+
+      try {
+        await program.methods
+          .withdraw(
+            Array.from(note.secret) as any,
+            Array.from(note.nullifierPreimage) as any,
+            Array.from(note.nullifierHash) as any,
+            0,
+            leafIndex,
+            proof.map((p) => Array.from(p) as any),
+            new BN(0)
+          )
+          .accounts({
+            nullifierShard: getNullifierShardPDA(programId, note.nullifierHash)[0],
+            pool: poolPDA,
+            recipient: recipient.publicKey,
+            relayer: authority.publicKey,
+            withdrawalCommit,
+            payer: authority.publicKey,
+          })
+          .rpc();
+        expect.fail("Should have rejected double-spend");
+      } catch (err: any) {
+        // The nullifier is already in its shard
+        expect(err.toString()).to.include("NullifierAlreadyUsed");
+      }
+    });
+
+    it("only migrates nullifiers that have a pre-shard account", async () => {
+      const note = pool0Notes[0]; // Spent into its shard, never had a PDA
+      try {
+        await program.methods
+          .migrateNullifier(Array.from(note.nullifierHash) as any)
+          .accountsPartial({
+            nullifierShard: getNullifierShardPDA(programId, note.nullifierHash)[0],
+            payer: authority.publicKey,
+          })
+          .rpc();
+        expect.fail("Should have required the legacy nullifier account");
+      } catch (err: any) {
+        expect(err.toString()).to.include("AccountNotInitialized");
+      }
+    });
+
+    it("withdraws second deposit from pool 0 (leaf index 1)", async () => {
+      const note = pool0Notes[1]; // Second deposit
+      const leafIndex = 1;
+      const poolId = 0;
+
+      const proof = computeMerkleProof(pool0Leaves, leafIndex);
+      const recipient = Keypair.generate();
+      const [poolPDA] = getPoolPDA(programId, poolId);
+
+      const recipientBefore = await provider.connection.getBalance(
+        recipient.publicKey
+      );
+
+      const withdrawalCommit = await commitWithdrawal(
+        note.secret,
+        note.nullifierPreimage,
+        recipient.publicKey
+      );
+
+      await program.methods
+        .withdraw(
+          Array.from(note.secret) as any,
+          Array.from(note.nullifierPreimage) as any,
+          Array.from(note.nullifierHash) as any,
+          0,
+          leafIndex,
+          proof.map((p) => Array.from(p) as any),
+          new BN(0)
+        )
+        .accounts({
+          nullifierShard: getNullifierShardPDA(programId, note.nullifierHash)[0],
+          pool: poolPDA,
+          recipient: recipient.publicKey,
+          relayer: authority.publicKey,
+          withdrawalCommit,
+          payer: authority.publicKey,
+        })
+        .rpc();
+
+      const recipientAfter = await provider.connection.getBalance(
+        recipient.publicKey
+      );
+      expect(recipientAfter - recipientBefore).to.equal(
+        POOL_DENOMINATIONS[poolId]
+      );
+    });
+    it("accepts a proof against a recent root after another deposit", async () => {
+      const note = pool0Notes[2]; // Third deposit (not yet withdrawn)
+      const leafIndex = 2;
+      const poolId = 0;
+
+      // Build the proof before the pool moves on
+      const staleLeaves = pool0Leaves.slice();
+      const staleRoot = computeIncrementalRoot(staleLeaves);
+      const proof = computeMerkleProof(staleLeaves, leafIndex);
+
+      // Another depositor lands first and changes the current root
+      const other = generateNote();
+      pool0Notes.push(other);
+      pool0Leaves.push(other.commitment);
+      await program.methods
+        .deposit(Array.from(other.commitment) as any, poolId, 3, null, null)
+        .accounts({
+          depositorClink: depositorClinkAccount,
+          depositor: authority.publicKey,
+        })
+        .rpc();
+
+      const [poolPDA] = getPoolPDA(programId, poolId);
+      const pool = await program.account.pool.fetch(poolPDA);
+      expect(Buffer.from(pool.currentRoot as number[])).to.not.deep.equal(
+        staleRoot
+      );
+
+      const known = await program.methods
+        .isKnownRoot(Array.from(staleRoot) as any)
+        .accounts({ pool: poolPDA })
+        .view();
+      expect(known).to.equal(true);
+
+      const recipient = Keypair.generate();
+      const withdrawalCommit = await commitWithdrawal(
+        note.secret,
+        note.nullifierPreimage,
+        recipient.publicKey
+      );
+
+      await program.methods
+        .withdraw(
+          Array.from(note.secret) as any,
+          Array.from(note.nullifierPreimage) as any,
+          Array.from(note.nullifierHash) as any,
+          0,
+          leafIndex,
+          proof.map((p) => Array.from(p) as any),
+          new BN(0)
+        )
+        .accounts({
+          nullifierShard: getNullifierShardPDA(programId, note.nullifierHash)[0],
+          pool: poolPDA,
+          recipient: recipient.publicKey,
+          relayer: authority.publicKey,
+          withdrawalCommit,
+          payer: authority.publicKey,
+        })
+        .rpc();
+
+      const recipientBalance = await provider.connection.getBalance(
+        recipient.publicKey
+      );
+      expect(recipientBalance).to.equal(POOL_DENOMINATIONS[poolId]);
+    });
+
+    it("lets a relayer withdraw to an unfunded wallet for a fee", async () => {
+      const note = pool0Notes[3]; // Deposited in the recent-root test
+      const leafIndex = 3;
+      const poolId = 0;
+      const fee = POOL_DENOMINATIONS[poolId] / 100; // 1%
+
+      const proof = computeMerkleProof(pool0Leaves, leafIndex);
+      const [poolPDA] = getPoolPDA(programId, poolId);
+      const recipient = Keypair.generate();
+      const relayer = Keypair.generate();
+
+      const withdrawalCommit = await commitWithdrawal(
+        note.secret,
+        note.nullifierPreimage,
+        recipient.publicKey,
+        relayer.publicKey,
+        fee
+      );
+
+      await program.methods
+        .withdraw(
+          Array.from(note.secret) as any,
+          Array.from(note.nullifierPreimage) as any,
+          Array.from(note.nullifierHash) as any,
+          0,
+          leafIndex,
+          proof.map((p) => Array.from(p) as any),
+          new BN(fee)
+        )
+        .accounts({
+          nullifierShard: getNullifierShardPDA(programId, note.nullifierHash)[0],
+          pool: poolPDA,
+          withdrawalCommit,
+          recipient: recipient.publicKey,
+          relayer: relayer.publicKey,
+          payer: authority.publicKey,
+        })
+        .rpc();
+
+      expect(
+        await provider.connection.getBalance(recipient.publicKey)
+      ).to.equal(POOL_DENOMINATIONS[poolId] - fee);
+      expect(await provider.connection.getBalance(relayer.publicKey)).to.equal(
+        fee
+      );
+    });
+
+    it("rejects a relayer fee above the configured maximum", async () => {
+      const note = generateNote();
+      const poolId = 0;
+      const fee = POOL_DENOMINATIONS[poolId] / 2; // 50% > 5% default cap
+      const recipient = Keypair.generate();
+      const [poolPDA] = getPoolPDA(programId, poolId);
+
+      const withdrawalCommit = await commitWithdrawal(
+        note.secret,
+        note.nullifierPreimage,
+        recipient.publicKey,
+        authority.publicKey,
+        fee
+      );
+
+      try {
+        await program.methods
+          .withdraw(
+            Array.from(note.secret) as any,
+            Array.from(note.nullifierPreimage) as any,
+            Array.from(note.nullifierHash) as any,
+            0,
+            0,
+            computeMerkleProof(pool0Leaves, 0).map((p) => Array.from(p) as any),
+            new BN(fee)
+          )
+          .accounts({
+            nullifierShard: getNullifierShardPDA(programId, note.nullifierHash)[0],
+            pool: poolPDA,
+            withdrawalCommit,
+            recipient: recipient.publicKey,
+            relayer: authority.publicKey,
+            payer: authority.publicKey,
+          })
+          .rpc();
+        expect.fail("Should have rejected excessive relayer fee");
+      } catch (err: any) {
+        expect(err.toString()).to.include("RelayerFeeTooHigh");
+      }
+    });
+
+    it("reports unknown roots as not accepted", async () => {
+      const [poolPDA] = getPoolPDA(programId, 0);
+      const known = await program.methods
+        .isKnownRoot(Array.from(sha256(Buffer.from("not a root"))) as any)
+        .accounts({ pool: poolPDA })
+        .view();
+      expect(known).to.equal(false);
+    });
+
+    describe("pre-shard nullifiers", () => {
+      // Withdrawn before sharding: its `NullifierAccount` is preloaded
+      const spent = legacyVectors.spentNote;
+      const note: Note = {
+        secret: Buffer.from(spent.secret, "hex"),
+        nullifierPreimage: Buffer.from(spent.nullifierPreimage, "hex"),
+        commitment: Buffer.from(spent.commitment, "hex"),
+        nullifierHash: Buffer.from(spent.nullifierHash, "hex"),
+      };
+      const poolId = 0;
+      let leafIndex: number;
+
+      async function withdrawSpent() {
+        const recipient = Keypair.generate();
+        const withdrawalCommit = await commitWithdrawal(
+          note.secret,
+          note.nullifierPreimage,
+          recipient.publicKey
+        );
+        return program.methods
+          .withdraw(
+            Array.from(note.secret) as any,
+            Array.from(note.nullifierPreimage) as any,
+            Array.from(note.nullifierHash) as any,
+            0,
+            leafIndex,
+            computeMerkleProof(pool0Leaves, leafIndex).map(
+              (p) => Array.from(p) as any
+            ),
+            new BN(0)
+          )
+          .accounts({
+            nullifierShard: getNullifierShardPDA(programId, note.nullifierHash)[0],
+            pool: getPoolPDA(programId, poolId)[0],
+            recipient: recipient.publicKey,
+            relayer: authority.publicKey,
+            withdrawalCommit,
+            payer: authority.publicKey,
+          })
+          .rpc();
+      }
+
+      before(async () => {
+        leafIndex = pool0Leaves.length;
+        pool0Notes.push(note);
+        pool0Leaves.push(note.commitment);
+        await program.methods
+          .deposit(Array.from(note.commitment) as any, poolId, leafIndex, null, null)
+          .accounts({
+            depositorClink: depositorClinkAccount,
+            depositor: authority.publicKey,
+          })
+          .rpc();
+      });
+
+      it("rejects a note whose nullifier has a pre-shard account", async () => {
+        expect(await isSpent(note.nullifierHash)).to.equal(false);
+        try {
+          await withdrawSpent();
+          expect.fail("Should have honoured the pre-shard nullifier");
+        } catch (err: any) {
+          expect(err.toString()).to.include("NullifierAlreadyUsed");
+        }
+      });
+
+      it("migrates the nullifier into its shard and closes the old account", async () => {
+        const [legacyNullifier] = getNullifierPDA(programId, note.nullifierHash);
+        await program.methods
+          .migrateNullifier(Array.from(note.nullifierHash) as any)
+          .accountsPartial({
+            legacyNullifier,
+            nullifierShard: getNullifierShardPDA(programId, note.nullifierHash)[0],
+            payer: authority.publicKey,
+          })
+          .rpc();
+
+        expect(await provider.connection.getAccountInfo(legacyNullifier)).to.equal(
+          null
+        );
+        expect(await isSpent(note.nullifierHash)).to.equal(true);
+
+        // Still spent, now through the shard
+        try {
+          await withdrawSpent();
+          expect.fail("Should have rejected the migrated nullifier");
+        } catch (err: any) {
+          expect(err.toString()).to.include("NullifierAlreadyUsed");
+        }
+      });
+    });
+  });
+
+  // ─── Withdraw (Groth16) ───────────────────────────────────────────
+
+  describe("withdraw_zk", () => {
+    const poolId = zkVectors.poolId;
+    const recipient = new PublicKey(Buffer.from(zkVectors.recipient, "hex"));
+    const relayer = new PublicKey(Buffer.from(zkVectors.relayer, "hex"));
+
+    const withdrawZk = (
+      proofA: string,
+      recipientKey: PublicKey,
+      pool = getPoolPDA(programId, poolId)[0]
+    ) =>
+      program.methods
+        .withdrawZk(
+          hexBytes(proofA) as any,
+          hexBytes(zkVectors.proof.b) as any,
+          hexBytes(zkVectors.proof.c) as any,
+          0,
+          hexBytes(zkVectors.root) as any,
+          hexBytes(zkVectors.nullifierHash) as any,
+          new BN(zkVectors.fee)
+        )
+        .accountsPartial({
+          nullifierShard: getNullifierShardPDA(
+            programId,
+            Buffer.from(zkVectors.nullifierHash, "hex")
+          )[0],
+          pool,
+          verifyingKey: getVerifyingKeyPDA(programId, "withdraw")[0],
+          recipient: recipientKey,
+          relayer,
+          payer: authority.publicKey,
+        })
+        .rpc();
+
+    before(async () => {
+      await initializeVerifyingKey("withdraw", zkVectors.verifyingKey);
+
+      // The fixture proof is for the root after this single deposit
+      const commitment = Buffer.from(zkVectors.note.commitment, "hex");
+      await depositNote({ commitment }, poolId, 0);
+    });
+
+    it("Poseidon pool root matches the fixture root", async () => {
+      const [poolPDA] = getPoolPDA(programId, poolId);
+      const pool = await program.account.pool.fetch(poolPDA);
+      expect(Buffer.from(pool.currentRoot as number[]).toString("hex")).to.equal(
+        zkVectors.root
+      );
+    });
+
+    it("rejects withdrawals from a SHA256 pool", async () => {
+      try {
+        await withdrawZk(zkVectors.proof.a, recipient, getPoolPDA(programId, 0)[0]);
+        expect.fail("Should have required a Poseidon pool");
+      } catch (err: any) {
+        expect(err.toString()).to.include("ProofNeedsPoseidonPool");
+      }
+    });
+
+    it("rejects a valid proof redirected to another recipient", async () => {
+      try {
+        await withdrawZk(zkVectors.proof.a, Keypair.generate().publicKey);
+        expect.fail("Should have rejected swapped recipient");
+      } catch (err: any) {
+        expect(err.toString()).to.include("InvalidProof");
+      }
+    });
+
+    it("rejects a tampered proof", async () => {
+      const tampered = Buffer.from(zkVectors.proof.a, "hex");
+      tampered[63] ^= 1;
+      try {
+        await withdrawZk(tampered.toString("hex"), recipient);
+        expect.fail("Should have rejected tampered proof");
+      } catch (err: any) {
+        expect(err.toString()).to.include("InvalidProof");
+      }
+    });
+
+    it("withdraws with a valid proof and pays the relayer fee", async () => {
+      const recipientBefore = await provider.connection.getBalance(recipient);
+      const relayerBefore = await provider.connection.getBalance(relayer);
+
+      await withdrawZk(zkVectors.proof.a, recipient);
+
+      const recipientAfter = await provider.connection.getBalance(recipient);
+      const relayerAfter = await provider.connection.getBalance(relayer);
+      expect(recipientAfter - recipientBefore).to.equal(
+        POOL_DENOMINATIONS[poolId] - zkVectors.fee
+      );
+      expect(relayerAfter - relayerBefore).to.equal(zkVectors.fee);
+
+      expect(await isSpent(Buffer.from(zkVectors.nullifierHash, "hex"))).to.equal(
+        true
+      );
+    });
+
+    it("rejects replaying the same proof", async () => {
+      try {
+        await withdrawZk(zkVectors.proof.a, recipient);
+        expect.fail("Should have rejected double-spend");
+      } catch (err: any) {
+        expect(err.toString()).to.include("NullifierAlreadyUsed");
+      }
+    });
+  });
+
+  // ─── Note Transfers ───────────────────────────────────────────────
+
+  describe("transfer_note", () => {
+    const poolId = transferVectors.poolId;
+    const denomination = 0.05 * LAMPORTS_PER_SOL;
+    const [poolPDA] = getPoolPDA(programId, poolId);
+
+    const transferNote = (newCommitment: string) =>
+      program.methods
+        .transferNote(
+          hexBytes(transferVectors.proof.a) as any,
+          hexBytes(transferVectors.proof.b) as any,
+          hexBytes(transferVectors.proof.c) as any,
+          0,
+          hexBytes(transferVectors.root) as any,
+          hexBytes(transferVectors.nullifierHash) as any,
+          hexBytes(newCommitment) as any
+        )
+        .accountsPartial({
+          nullifierShard: getNullifierShardPDA(
+            programId,
+            Buffer.from(transferVectors.nullifierHash, "hex")
+          )[0],
+          pool: poolPDA,
+          treeArchive: null,
+          verifyingKey: getVerifyingKeyPDA(programId, "transfer")[0],
+          commitmentMarker: PublicKey.findProgramAddressSync(
+            [
+              Buffer.from("commitment"),
+              Buffer.from(newCommitment, "hex"),
+            ],
+            programId
+          )[0],
+          commitmentLeaf: getLeafPDA(programId, poolId, 1)[0],
+          payer: authority.publicKey,
+        })
+        .rpc();
+
+    before(async () => {
+      await initializeVerifyingKey("transfer", transferVectors.verifyingKey);
+      await createPool(poolId, denomination, transferVectors.treeDepth, "poseidon");
+
+      // The fixture proof is for the root after this single deposit
+      const commitment = Buffer.from(transferVectors.note.commitment, "hex");
+      await depositNote({ commitment }, poolId, 0);
+    });
+
+    it("rejects transfers in a SHA256 pool", async () => {
+      const [sha256PoolPDA] = getPoolPDA(programId, 0);
+      const sha256Pool = await program.account.pool.fetch(sha256PoolPDA);
+      try {
+        await program.methods
+          .transferNote(
+            hexBytes(transferVectors.proof.a) as any,
+            hexBytes(transferVectors.proof.b) as any,
+            hexBytes(transferVectors.proof.c) as any,
+            0,
+            hexBytes(transferVectors.root) as any,
+            hexBytes(transferVectors.nullifierHash) as any,
+            hexBytes(transferVectors.newNote.commitment) as any
+          )
+          .accountsPartial({
+            nullifierShard: getNullifierShardPDA(
+              programId,
+              Buffer.from(transferVectors.nullifierHash, "hex")
+            )[0],
+            pool: sha256PoolPDA,
+            treeArchive: null,
+            verifyingKey: getVerifyingKeyPDA(programId, "transfer")[0],
+            commitmentLeaf: getLeafPDA(
+              programId,
+              0,
+              sha256Pool.nextIndex,
+              sha256Pool.treeIndex
+            )[0],
+            payer: authority.publicKey,
+          })
+          .rpc();
+        expect.fail("Should have required a Poseidon pool");
+      } catch (err: any) {
+        expect(err.toString()).to.include("ProofNeedsPoseidonPool");
+      }
+    });
+
+    it("rejects a proof for a different new commitment", async () => {
+      try {
+        await transferNote(generateNote().commitment.toString("hex"));
+        expect.fail("Should have rejected a swapped commitment");
+      } catch (err: any) {
+        expect(err.toString()).to.include("InvalidProof");
+      }
+    });
+
+    it("holds transfers to the pool's withdrawal delay", async () => {
+      await program.methods
+        .updateWithdrawalDelay(poolId, 0, 3600)
+        .accounts({ authority: authority.publicKey })
+        .rpc();
+      try {
+        await transferNote(transferVectors.newNote.commitment);
+        expect.fail("Should have enforced the delay");
+      } catch (err: any) {
+        expect(err.toString()).to.include("WithdrawalTooEarly");
+      }
+      await program.methods
+        .updateWithdrawalDelay(poolId, 0, 0)
+        .accounts({ authority: authority.publicKey })
+        .rpc();
+    });
+
+    it("spends a note into a new commitment without moving SOL", async () => {
+      const [vaultPDA] = getVaultPDA(programId, poolId);
+      const vaultBefore = await provider.connection.getBalance(vaultPDA);
+
+      await transferNote(transferVectors.newNote.commitment);
+
+      expect(await provider.connection.getBalance(vaultPDA)).to.equal(
+        vaultBefore
+      );
+      // The old note counts as spent but not paid out: its value stays in
+      // the pool, now backing the new note
+      const pool = await program.account.pool.fetch(poolPDA);
+      expect(pool.nextIndex).to.equal(2);
+      expect(pool.withdrawalCount).to.equal(1);
+      expect(pool.totalWithdrawals.toNumber()).to.equal(0);
+      expect(pool.totalDeposits.toNumber()).to.equal(1);
+
+      const leaf = await program.account.commitmentLeaf.fetch(
+        getLeafPDA(programId, poolId, 1)[0]
+      );
+      expect(Buffer.from(leaf.commitment as number[]).toString("hex")).to.equal(
+        transferVectors.newNote.commitment
+      );
+    });
+
+    it("rejects spending the same note twice", async () => {
+      try {
+        await transferNote(transferVectors.newNote.commitment);
+        expect.fail("Should have rejected double-spend");
+      } catch (err: any) {
+        expect(err.toString()).to.include("NullifierAlreadyUsed");
+      }
+    });
+  });
+
+  // ─── Shielded Pool (transact) ─────────────────────────────────────
+
+  describe("transact", () => {
+    const poolId = transactVectors.poolId;
+    const [poolPDA] = getPoolPDA(programId, poolId);
+    type Step = typeof transactVectors.deposit;
+
+    let lookupTable: AddressLookupTableAccount;
+
+    const commitmentMarker = (commitment: string) =>
+      PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("commitment"),
+          Buffer.from(commitment, "hex"),
+        ],
+        programId
+      )[0];
+
+    const transact = async (step: Step, proofA: string = step.proof.a) => {
+      const instruction = await program.methods
+        .transact(
+          0,
+          {
+            proofA: hexBytes(proofA),
+            proofB: hexBytes(step.proof.b),
+            proofC: hexBytes(step.proof.c),
+            root: hexBytes(step.root),
+            inputNullifiers: step.inputNullifiers.map(hexBytes),
+            outputCommitments: step.outputCommitments.map(hexBytes),
+          } as any,
+          new BN(step.extAmount),
+          new BN(step.fee)
+        )
+        .accountsPartial({
+          pool: poolPDA,
+          treeArchive: null,
+          verifyingKey: getVerifyingKeyPDA(programId, "transact")[0],
+          nullifierShard0: getNullifierShardPDA(
+            programId,
+            Buffer.from(step.inputNullifiers[0], "hex")
+          )[0],
+          nullifierShard1: getNullifierShardPDA(
+            programId,
+            Buffer.from(step.inputNullifiers[1], "hex")
+          )[0],
+          legacyNullifier0: getNullifierPDA(
+            programId,
+            Buffer.from(step.inputNullifiers[0], "hex")
+          )[0],
+          legacyNullifier1: getNullifierPDA(
+            programId,
+            Buffer.from(step.inputNullifiers[1], "hex")
+          )[0],
+          recipient: new PublicKey(Buffer.from(step.recipient, "hex")),
+          relayer: new PublicKey(Buffer.from(step.relayer, "hex")),
+          commitmentMarker0: commitmentMarker(step.outputCommitments[0]),
+          commitmentMarker1: commitmentMarker(step.outputCommitments[1]),
+          payer: authority.publicKey,
+          clinkMint: step.extAmount > 0 ? clinkMint : null,
+          payerClink: step.extAmount > 0 ? depositorClinkAccount : null,
+          treasury: step.extAmount > 0 ? getTreasuryPDA(programId)[0] : null,
+          tokenProgram: step.extAmount > 0 ? TOKEN_PROGRAM_ID : null,
+        })
+        .instruction();
+
+      // Too many accounts for a legacy transaction: the fixed ones come from
+      // the lookup table
+      const { blockhash } = await provider.connection.getLatestBlockhash();
+      const message = new TransactionMessage({
+        payerKey: authority.publicKey,
+        recentBlockhash: blockhash,
+        instructions: [
+          ComputeBudgetProgram.setComputeUnitLimit({ units: 600_000 }),
+          instruction,
+        ],
+      }).compileToV0Message([lookupTable]);
+      return provider.sendAndConfirm(new VersionedTransaction(message), [], {
+        commitment: "confirmed",
+      });
+    };
+
+    before(async () => {
+      await initializeVerifyingKey("transact", transactVectors.verifyingKey);
+      await createPool(poolId, null, transactVectors.treeDepth, "poseidon");
+      // One deposit per hour: the fixture deposit below fills the window
+      await program.methods
+        .updateDepositLimits(poolId, 3600, 1, 0)
+        .accounts({ authority: authority.publicKey })
+        .rpc();
+
+      const recentSlot = await provider.connection.getSlot("finalized");
+      const [createTable, tableAddress] = AddressLookupTableProgram.createLookupTable({
+        authority: authority.publicKey,
+        payer: authority.publicKey,
+        recentSlot,
+      });
+      const extendTable = AddressLookupTableProgram.extendLookupTable({
+        lookupTable: tableAddress,
+        authority: authority.publicKey,
+        payer: authority.publicKey,
+        addresses: [
+          getConfigPDA(programId)[0],
+          poolPDA,
+          getRegistryPDA(programId)[0],
+          getVaultPDA(programId, poolId)[0],
+          PublicKey.findProgramAddressSync(
+            [Buffer.from("deposit_window"), Buffer.from([poolId])],
+            programId
+          )[0],
+          getVerifyingKeyPDA(programId, "transact")[0],
+          clinkMint,
+          depositorClinkAccount,
+          getTreasuryPDA(programId)[0],
+          PublicKey.findProgramAddressSync(
+            [Buffer.from("__event_authority")],
+            programId
+          )[0],
+          SystemProgram.programId,
+          TOKEN_PROGRAM_ID,
+        ],
+      });
+      await provider.sendAndConfirm(new Transaction().add(createTable, extendTable));
+
+      // A table serves its addresses from the slot after it was extended
+      const extendedAt = await provider.connection.getSlot("confirmed");
+      while ((await provider.connection.getSlot("confirmed")) <= extendedAt) {
+        await new Promise((resolve) => setTimeout(resolve, 200));
+      }
+      lookupTable = (await provider.connection.getAddressLookupTable(tableAddress))
+        .value!;
+    });
+
+    it("rejects shielded pools hashed with SHA256", async () => {
+      const sha256PoolId = 11;
+      await program.methods
+        .registerShieldedPool(sha256PoolId, transactVectors.treeDepth)
+        .accounts({ authority: authority.publicKey })
+        .rpc();
+      try {
+        await program.methods
+          .initializePool(sha256PoolId, { sha256: {} })
+          .accounts({ authority: authority.publicKey })
+          .rpc();
+        expect.fail("Should have required Poseidon");
+      } catch (err: any) {
+        expect(err.toString()).to.include("InvalidHashFunction");
+      }
+    });
+
+    it("rejects fixed-denomination deposits into a shielded pool", async () => {
+      try {
+        await program.methods
+          .queueDeposit(Array.from(generateNote().commitment) as any, poolId, null, null)
+          .accounts({
+            depositorClink: depositorClinkAccount,
+            depositor: authority.publicKey,
+          })
+          .rpc();
+        expect.fail("Should have rejected a fixed deposit");
+      } catch (err: any) {
+        expect(err.toString()).to.include("InvalidPoolMode");
+      }
+    });
+
+    it("deposits an arbitrary amount into two new notes", async () => {
+      const [vaultPDA] = getVaultPDA(programId, poolId);
+      const vaultBefore = await provider.connection.getBalance(vaultPDA);
+      // initialize_pool pre-funded the vault's rent
+      expect(vaultBefore).to.equal(
+        await provider.connection.getMinimumBalanceForRentExemption(0)
+      );
+
+      const signature = await transact(transactVectors.deposit);
+
+      const vaultAfter = await provider.connection.getBalance(vaultPDA);
+      expect(vaultAfter - vaultBefore).to.equal(transactVectors.deposit.extAmount);
+
+      const pool = await program.account.pool.fetch(poolPDA);
+      expect(pool.nextIndex).to.equal(2);
+      expect(Buffer.from(pool.currentRoot as number[]).toString("hex")).to.equal(
+        transactVectors.withdraw.root
+      );
+
+      const events = await cpiEvents(signature);
+      expect(events.map((e) => e.name)).to.deep.equal([
+        "depositEvent",
+        "depositEvent",
+        "transactEvent",
+      ]);
+      events.slice(0, 2).forEach((e, i) => {
+        expect(Buffer.from(e.data.commitment as number[]).toString("hex")).to.equal(
+          transactVectors.deposit.outputCommitments[i]
+        );
+        // Both outputs carry the root recorded after the pair
+        expect(Buffer.from(e.data.root as number[]).toString("hex")).to.equal(
+          transactVectors.withdraw.root
+        );
+      });
+
+      // Each output claimed its marker, so neither commitment can be deposited again
+      for (const commitment of transactVectors.deposit.outputCommitments) {
+        const marker = await provider.connection.getAccountInfo(
+          commitmentMarker(commitment)
+        );
+        expect(marker!.owner.toBase58()).to.equal(programId.toBase58());
+      }
+      expect(events[2].data.extAmount.toNumber()).to.equal(
+        transactVectors.deposit.extAmount
+      );
+    });
+
+    it("holds deposits to the pool's deposit limits", async () => {
+      // Replaying the deposit fails on the window before its spent nullifiers
+      try {
+        await transact(transactVectors.deposit);
+        expect.fail("Should have hit the pool's limit");
+      } catch (err: any) {
+        expect(err.toString()).to.include("PoolDepositLimitReached");
+      }
+      await program.methods
+        .updateDepositLimits(poolId, 0, 0, 0)
+        .accounts({ authority: authority.publicKey })
+        .rpc();
+    });
+
+    it("rejects a proof for a different public amount", async () => {
+      const tampered = { ...transactVectors.withdraw, extAmount: -500_000_000 };
+      try {
+        await transact(tampered);
+        expect.fail("Should have rejected a mismatched amount");
+      } catch (err: any) {
+        expect(err.toString()).to.include("InvalidProof");
+      }
+    });
+
+    it("withdraws part of a note and pays the relayer", async () => {
+      const step = transactVectors.withdraw;
+      const recipient = new PublicKey(Buffer.from(step.recipient, "hex"));
+      const relayer = new PublicKey(Buffer.from(step.relayer, "hex"));
+      const recipientBefore = await provider.connection.getBalance(recipient);
+      const relayerBefore = await provider.connection.getBalance(relayer);
+
+      await transact(step);
+
+      expect(
+        (await provider.connection.getBalance(recipient)) - recipientBefore
+      ).to.equal(-step.extAmount);
+      expect(
+        (await provider.connection.getBalance(relayer)) - relayerBefore
+      ).to.equal(step.fee);
+
+      const pool = await program.account.pool.fetch(poolPDA);
+      expect(pool.nextIndex).to.equal(4);
+    });
+
+    it("rejects spending the same nullifier twice", async () => {
+      try {
+        await transact(transactVectors.withdraw);
+        expect.fail("Should have rejected double-spend");
+      } catch (err: any) {
+        expect(err.toString()).to.include("NullifierAlreadyUsed");
+      }
+    });
+
+    it("leaves less than rent in notes without touching the vault's rent", async () => {
+      const [vaultPDA] = getVaultPDA(programId, poolId);
+      await transact(transactVectors.drain);
+
+      const pool = await program.account.pool.fetch(poolPDA);
+      expect(pool.shieldedBalance.toNumber()).to.equal(100_000);
+      expect(await provider.connection.getBalance(vaultPDA)).to.equal(
+        (await provider.connection.getMinimumBalanceForRentExemption(0)) + 100_000
+      );
+    });
+  });
+
+  // ─── Pool Audit ───────────────────────────────────────────────────
+
+  describe("audit_pool", () => {
+    const audit = async (poolId: number) => {
+      const signature = await program.methods
+        .auditPool()
+        .accountsPartial({
+          pool: getPoolPDA(programId, poolId)[0],
+          tokenVault: null,
+        })
+        .rpc({ commitment: "confirmed" });
+      const [event] = await cpiEvents(signature);
+      expect(event.name).to.equal("poolAuditEvent");
+      return event.data;
+    };
+
+    it("balances a fixed pool's vault against its outstanding notes", async () => {
+      const poolId = 0;
+      const pool = await program.account.pool.fetch(getPoolPDA(programId, poolId)[0]);
+      const result = await audit(poolId);
+
+      const outstanding = pool.totalDeposits.sub(pool.totalWithdrawals).toNumber();
+      expect(result.outstandingNotes.toNumber()).to.equal(outstanding);
+      expect(result.expected.toNumber()).to.equal(outstanding * POOL_DENOMINATIONS[poolId]);
+      expect(result.held.toNumber()).to.equal(
+        await provider.connection.getBalance(getVaultPDA(programId, poolId)[0])
+      );
+      expect(result.status).to.deep.equal({ balanced: {} });
+    });
+
+    it("tracks a shielded pool's balance through transact", async () => {
+      const { deposit, withdraw, drain } = transactVectors;
+      const result = await audit(transactVectors.poolId);
+      expect(result.expected.toNumber()).to.equal(
+        [deposit, withdraw, drain].reduce((sum, step) => sum + step.extAmount - step.fee, 0)
+      );
+      expect(result.totalDeposits.toNumber()).to.equal(1);
+      expect(result.totalWithdrawals.toNumber()).to.equal(2);
+      expect(result.status).to.deep.equal({ balanced: {} });
+    });
+
+    it("reports a stray transfer into the vault", async () => {
+      const poolId = 0;
+      await provider.sendAndConfirm(
+        new Transaction().add(
+          SystemProgram.transfer({
+            fromPubkey: authority.publicKey,
+            toPubkey: getVaultPDA(programId, poolId)[0],
+            lamports: LAMPORTS_PER_SOL,
+          })
+        )
+      );
+      const result = await audit(poolId);
+      // On top of the vault's rent
+      expect(result.held.sub(result.expected).toNumber()).to.equal(
+        LAMPORTS_PER_SOL + (await provider.connection.getMinimumBalanceForRentExemption(0))
+      );
+      expect(result.status).to.deep.equal({ surplus: {} });
+    });
+  });
+
+  // ─── Treasury ─────────────────────────────────────────────────────
+
+  describe("treasury", () => {
+    it("lets the authority withdraw collected fees", async () => {
+      const [treasuryPDA] = getTreasuryPDA(programId);
+      const treasuryBefore = await getAccount(provider.connection, treasuryPDA);
+      expect(Number(treasuryBefore.amount)).to.be.greaterThan(0);
+
+      const destinationBefore = await getAccount(
+        provider.connection,
+        depositorClinkAccount
+      );
+
+      await program.methods
+        .withdrawTreasury(new BN(FEE_AMOUNT))
+        .accounts({
+          destination: depositorClinkAccount,
+          authority: authority.publicKey,
+        })
+        .rpc();
+
+      const treasuryAfter = await getAccount(provider.connection, treasuryPDA);
+      const destinationAfter = await getAccount(
+        provider.connection,
+        depositorClinkAccount
+      );
+      expect(Number(treasuryBefore.amount - treasuryAfter.amount)).to.equal(
+        FEE_AMOUNT
+      );
+      expect(
+        Number(destinationAfter.amount - destinationBefore.amount)
+      ).to.equal(FEE_AMOUNT);
+    });
+
+    it("rejects treasury withdrawals from non-authority", async () => {
+      const intruder = Keypair.generate();
+      try {
+        await program.methods
+          .withdrawTreasury(new BN(1))
+          .accounts({
+            destination: depositorClinkAccount,
+            authority: intruder.publicKey,
+          })
+          .signers([intruder])
+          .rpc();
+        expect.fail("Should have rejected non-authority");
+      } catch (err: any) {
+        expect(err.toString()).to.include("Unauthorized");
+      }
+    });
+
+    it("burns deposit fees when burn_fees is enabled", async () => {
+      const [treasuryPDA] = getTreasuryPDA(programId);
+      await program.methods
+        .updateBurnFees(true)
+        .accounts({ authority: authority.publicKey })
+        .rpc();
+
+      const supplyBefore = (await getMint(provider.connection, clinkMint))
+        .supply;
+      const treasuryBefore = await getAccount(provider.connection, treasuryPDA);
+
+      const note = generateNote();
+      pool1Notes.push(note);
+      pool1Leaves.push(note.commitment);
+      await program.methods
+        .deposit(Array.from(note.commitment) as any, 1, pool1Leaves.length - 1, null, null)
+        .accounts({
+          depositorClink: depositorClinkAccount,
+          depositor: authority.publicKey,
+        })
+        .rpc();
+
+      const supplyAfter = (await getMint(provider.connection, clinkMint))
+        .supply;
+      const treasuryAfter = await getAccount(provider.connection, treasuryPDA);
+      expect(Number(supplyBefore - supplyAfter)).to.equal(FEE_AMOUNT);
+      expect(treasuryAfter.amount).to.equal(treasuryBefore.amount);
+
+      await program.methods
+        .updateBurnFees(false)
+        .accounts({ authority: authority.publicKey })
+        .rpc();
+    });
+  });
+
+  // ─── Pool Sunset / Close ──────────────────────────────────────────
+
+  describe("sunset_pool / close_pool", () => {
+    const poolId = 1;
+
+    it("refuses to close a pool with outstanding notes", async () => {
+      try {
+        await program.methods
+          .closePool(poolId)
+          .accounts({ authority: authority.publicKey })
+          .rpc();
+        expect.fail("Should have refused to strand deposits");
+      } catch (err: any) {
+        expect(err.toString()).to.include("PoolNotDrained");
+      }
+    });
+
+    it("blocks deposits once sunset", async () => {
+      await program.methods
+        .sunsetPool(poolId)
+        .accounts({ authority: authority.publicKey })
+        .rpc();
+
+      const [poolPDA] = getPoolPDA(programId, poolId);
+      const pool = await program.account.pool.fetch(poolPDA);
+      expect(pool.sunset).to.equal(true);
+
+      const note = generateNote();
+      try {
+        await program.methods
+          .deposit(Array.from(note.commitment) as any, poolId, pool.nextIndex, null, null)
+          .accounts({
+            depositorClink: depositorClinkAccount,
+            depositor: authority.publicKey,
+          })
+          .rpc();
+        expect.fail("Should have rejected deposit into sunset pool");
+      } catch (err: any) {
+        expect(err.toString()).to.include("PoolSunset");
+      }
+    });
+
+    it("keeps withdrawals open, then closes once drained", async () => {
+      const [poolPDA] = getPoolPDA(programId, poolId);
+      const leafIndex = 1; // Deposited in the burn-fees test
+      await withdrawNote(pool1Notes[leafIndex], poolId, pool1Leaves, leafIndex);
+
+      const pool = await program.account.pool.fetch(poolPDA);
+      expect(pool.withdrawalCount).to.equal(pool.nextIndex);
+
+      await program.methods
+        .closePool(poolId)
+        .accounts({ authority: authority.publicKey })
+        .rpc();
+      expect(await provider.connection.getAccountInfo(poolPDA)).to.equal(null);
+    });
+  });
+
+  // ─── Emergency Pause ──────────────────────────────────────────────
+
+  describe("pause / unpause", () => {
+    const guardian = Keypair.generate();
+    const [configPDA] = getConfigPDA(programId);
+
+    it("lets the guardian pause deposits but not unpause them", async () => {
+      await program.methods
+        .updateGuardian(guardian.publicKey)
+        .accounts({ authority: authority.publicKey })
+        .rpc();
+      await program.methods
+        .pause(true, false)
+        .accounts({ signer: guardian.publicKey })
+        .signers([guardian])
+        .rpc();
+      expect((await program.account.protocolConfig.fetch(configPDA)).pausedDeposits)
+        .to.equal(true);
+
+      try {
+        await program.methods
+          .deposit(Array.from(generateNote().commitment) as any, 0, pool0Leaves.length, null, null)
+          .accounts({
+            depositorClink: depositorClinkAccount,
+            depositor: authority.publicKey,
+          })
+          .rpc();
+        expect.fail("Should have rejected deposit while paused");
+      } catch (err: any) {
+        expect(err.toString()).to.include("DepositsPaused");
+      }
+
+      try {
+        await program.methods
+          .unpause(true, false)
+          .accounts({ authority: guardian.publicKey })
+          .signers([guardian])
+          .rpc();
+        expect.fail("Guardian should not be able to unpause");
+      } catch (err: any) {
+        expect(err.toString()).to.include("Unauthorized");
+      }
+
+      await program.methods
+        .unpause(true, false)
+        .accounts({ authority: authority.publicKey })
+        .rpc();
+      expect((await program.account.protocolConfig.fetch(configPDA)).pausedDeposits)
+        .to.equal(false);
+    });
+
+    it("blocks withdrawals while paused, then lets them through", async () => {
+      const poolId = 0;
+      const note = generateNote();
+      pool0Notes.push(note);
+      pool0Leaves.push(note.commitment);
+      const leafIndex = pool0Leaves.length - 1;
+      await program.methods
+        .deposit(Array.from(note.commitment) as any, poolId, leafIndex, null, null)
+        .accounts({
+          depositorClink: depositorClinkAccount,
+          depositor: authority.publicKey,
+        })
+        .rpc();
+
+      await program.methods
+        .pause(false, true)
+        .accounts({ signer: guardian.publicKey })
+        .signers([guardian])
+        .rpc();
+
+      const recipient = Keypair.generate();
+      const withdrawalCommit = await commitWithdrawal(
+        note.secret,
+        note.nullifierPreimage,
+        recipient.publicKey
+      );
+      const withdraw = () =>
+        program.methods
+          .withdraw(
+            Array.from(note.secret) as any,
+            Array.from(note.nullifierPreimage) as any,
+            Array.from(note.nullifierHash) as any,
+            0,
+            leafIndex,
+            computeMerkleProof(pool0Leaves, leafIndex).map(
+              (p) => Array.from(p) as any
+            ),
+            new BN(0)
+          )
+          .accounts({
+            nullifierShard: getNullifierShardPDA(programId, note.nullifierHash)[0],
+            pool: getPoolPDA(programId, poolId)[0],
+            withdrawalCommit,
+            recipient: recipient.publicKey,
+            relayer: authority.publicKey,
+            payer: authority.publicKey,
+          })
+          .rpc();
+
+      try {
+        await withdraw();
+        expect.fail("Should have rejected withdrawal while paused");
+      } catch (err: any) {
+        expect(err.toString()).to.include("WithdrawalsPaused");
+      }
+
+      await program.methods
+        .unpause(false, true)
+        .accounts({ authority: authority.publicKey })
+        .rpc();
+      await withdraw();
+      expect(await provider.connection.getBalance(recipient.publicKey)).to.equal(
+        POOL_DENOMINATIONS[poolId]
+      );
+    });
+
+    it("rejects pausing from other keys", async () => {
+      const intruder = Keypair.generate();
+      try {
+        await program.methods
+          .pause(true, true)
+          .accounts({ signer: intruder.publicKey })
+          .signers([intruder])
+          .rpc();
+        expect.fail("Should have rejected non-guardian");
+      } catch (err: any) {
+        expect(err.toString()).to.include("Unauthorized");
+      }
+    });
+
+    it("rejects a PDA as guardian", async () => {
+      try {
+        await program.methods
+          .updateGuardian(configPDA)
+          .accounts({ authority: authority.publicKey })
+          .rpc();
+        expect.fail("Should have rejected a guardian that cannot sign");
+      } catch (err: any) {
+        expect(err.toString()).to.include("InvalidGuardian");
+      }
+    });
+  });
+
+  // ─── Authority Transfer ───────────────────────────────────────────
+
+  describe("propose_authority / accept_authority", () => {
+    const [configPDA] = getConfigPDA(programId);
+    const members = [Keypair.generate(), Keypair.generate()];
+    const [signerSetPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("authority_signers"), authority.publicKey.toBuffer(), Buffer.from([0])],
+      programId
+    );
+    const coSigner = (member: Keypair) => ({
+      pubkey: member.publicKey,
+      isSigner: true,
+      isWritable: false,
+    });
+
+    it("transfers the authority only once the new key accepts", async () => {
+      const newAuthority = Keypair.generate();
+      await program.methods
+        .updateGuardian(authority.publicKey)
+        .accounts({ authority: authority.publicKey })
+        .rpc();
+      await program.methods
+        .proposeAuthority(newAuthority.publicKey)
+        .accounts({ authority: authority.publicKey })
+        .rpc();
+
+      try {
+        await program.methods
+          .acceptAuthority()
+          .accounts({ authority: authority.publicKey })
+          .rpc();
+        expect.fail("Should have rejected accept from another key");
+      } catch (err: any) {
+        expect(err.toString()).to.include("Unauthorized");
+      }
+
+      await program.methods
+        .acceptAuthority()
+        .accounts({ authority: newAuthority.publicKey })
+        .signers([newAuthority])
+        .rpc();
+      let config = await program.account.protocolConfig.fetch(configPDA);
+      expect(config.authority.toBase58()).to.equal(newAuthority.publicKey.toBase58());
+      expect(config.pendingAuthority).to.be.null;
+      // The guardian stays until the new authority replaces it
+      expect(config.guardian.toBase58()).to.equal(authority.publicKey.toBase58());
+
+      // Hand it back
+      await program.methods
+        .proposeAuthority(authority.publicKey)
+        .accounts({ authority: newAuthority.publicKey })
+        .signers([newAuthority])
+        .rpc();
+      await program.methods
+        .acceptAuthority()
+        .accounts({ authority: authority.publicKey })
+        .rpc();
+      config = await program.account.protocolConfig.fetch(configPDA);
+      expect(config.authority.toBase58()).to.equal(authority.publicKey.toBase58());
+    });
+
+    it("rejects accepting with nothing proposed", async () => {
+      try {
+        await program.methods
+          .acceptAuthority()
+          .accounts({ authority: authority.publicKey })
+          .rpc();
+        expect.fail("Should have rejected accept without a proposal");
+      } catch (err: any) {
+        expect(err.toString()).to.include("NoPendingAuthority");
+      }
+    });
+
+    it("rejects signer sets with a bad threshold", async () => {
+      try {
+        await program.methods
+          .createAuthoritySigners(1, 3, [authority.publicKey, members[0].publicKey])
+          .accounts({ payer: authority.publicKey })
+          .rpc();
+        expect.fail("Should have rejected threshold above the key count");
+      } catch (err: any) {
+        expect(err.toString()).to.include("InvalidSignerSet");
+      }
+    });
+
+    it("hands the authority to a 2-of-3 signer set", async () => {
+      await program.methods
+        .createAuthoritySigners(0, 2, [
+          authority.publicKey,
+          members[0].publicKey,
+          members[1].publicKey,
+        ])
+        .accounts({ payer: authority.publicKey })
+        .rpc();
+      await program.methods
+        .proposeAuthority(signerSetPDA)
+        .accounts({ authority: authority.publicKey })
+        .rpc();
+      await program.methods
+        .acceptAuthority()
+        .accounts({ authority: authority.publicKey, authoritySigners: signerSetPDA })
+        .remainingAccounts([coSigner(members[0])])
+        .signers([members[0]])
+        .rpc();
+
+      const config = await program.account.protocolConfig.fetch(configPDA);
+      expect(config.authority.toBase58()).to.equal(signerSetPDA.toBase58());
+    });
+
+    it("requires the threshold for authority-only instructions", async () => {
+      try {
+        await program.methods
+          .updateFee(new BN(FEE_AMOUNT))
+          .accounts({ authority: authority.publicKey, authoritySigners: signerSetPDA })
+          .rpc();
+        expect.fail("Should have required a second signer");
+      } catch (err: any) {
+        expect(err.toString()).to.include("NotEnoughSigners");
+      }
+
+      try {
+        await program.methods
+          .updateFee(new BN(FEE_AMOUNT))
+          .accounts({ authority: authority.publicKey })
+          .rpc();
+        expect.fail("Should have required the signer set");
+      } catch (err: any) {
+        expect(err.toString()).to.include("Unauthorized");
+      }
+
+      await program.methods
+        .updateFee(new BN(FEE_AMOUNT))
+        .accounts({ authority: members[0].publicKey, authoritySigners: signerSetPDA })
+        .remainingAccounts([coSigner(members[1])])
+        .signers([members[0], members[1]])
+        .rpc();
+    });
+
+    it("transfers the authority back with the set's approval", async () => {
+      await program.methods
+        .proposeAuthority(authority.publicKey)
+        .accounts({ authority: authority.publicKey, authoritySigners: signerSetPDA })
+        .remainingAccounts([coSigner(members[1])])
+        .signers([members[1]])
+        .rpc();
+      await program.methods
+        .acceptAuthority()
+        .accounts({ authority: authority.publicKey })
+        .rpc();
+
+      const config = await program.account.protocolConfig.fetch(configPDA);
+      expect(config.authority.toBase58()).to.equal(authority.publicKey.toBase58());
+    });
   });
 
   // ─── Update Fee ───────────────────────────────────────────────────